@@ -0,0 +1,78 @@
+//! Client configuration, as found in `client.conf`.
+
+use anyhow::{Context, Result};
+
+use filer_tokio::auth::read_token_file;
+use filer_tokio::{FilerClient, NodeAddr};
+
+use serde::Deserialize;
+
+use std::path::{Path, PathBuf};
+
+/// Where the configuration is installed to.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/memflow/client.conf";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// Address of the node to connect to.
+    ///
+    /// This is either a TCP address, optionally prefixed by `tcp://`, or the path of a unix domain
+    /// socket prefixed by `unix:`.
+    pub socket_addr: Option<String>,
+    /// Key file whose first token is presented to the node.
+    pub auth_token_file: Option<PathBuf>,
+}
+
+impl ClientConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        serde_json::from_str(&config).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    /// Credentials to present to the node, which are empty without a token file.
+    pub fn credentials(&self) -> Result<Vec<u8>> {
+        match &self.auth_token_file {
+            Some(path) => read_token_file(path)
+                .with_context(|| format!("unable to read token from {}", path.display())),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+/// Connect to the node listening on `addr`, in any of the forms of `ClientConfig::socket_addr`.
+pub fn connect(addr: &str, credentials: &[u8]) -> Result<FilerClient> {
+    let client = match NodeAddr::parse(addr)? {
+        NodeAddr::Tcp(tcp_addr) => FilerClient::connect(tcp_addr, credentials),
+        #[cfg(unix)]
+        NodeAddr::Unix(path) => FilerClient::connect_unix(path, credentials),
+        #[cfg(not(unix))]
+        NodeAddr::Unix(_) => {
+            anyhow::bail!("unix domain sockets are not supported on this platform")
+        }
+    };
+
+    client.with_context(|| format!("unable to connect to {addr}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parse_shipped_config() {
+        let config: ClientConfig = serde_json::from_str(include_str!("../../client.conf")).unwrap();
+        assert_eq!(config.socket_addr.as_deref(), Some("tcp://127.0.0.1:8000"));
+        assert!(config.auth_token_file.is_none());
+    }
+
+    #[test]
+    pub fn connect_rejects_http() {
+        match connect("http://127.0.0.1:8000", &[]) {
+            Err(e) => assert!(e.to_string().contains("HTTP address")),
+            Ok(_) => panic!("connected to an HTTP address"),
+        }
+    }
+}
@@ -0,0 +1,629 @@
+//! Commands for inspecting and driving a filer `Node` without a FUSE mount.
+//!
+//! Every command works on any `Frontend`, be it a node in the same process, or a `FilerClient`
+//! connected to a remote one. Output is either human readable, or JSON for use in scripts.
+
+use anyhow::{bail, Result};
+
+use cglue::tuple::CTup2;
+
+use filer::prelude::v1::{Error, *};
+
+use serde::Serialize;
+
+use std::io::Write;
+
+pub mod config;
+pub mod shell;
+
+/// Leaves are read in chunks of this size.
+const CHUNK_LEN: usize = 1 << 16;
+
+/// Bytes shown on a single line of `hexdump`.
+const HEXDUMP_WIDTH: usize = 16;
+
+#[derive(Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub is_branch: bool,
+}
+
+#[derive(Serialize)]
+struct Metadata {
+    is_branch: bool,
+    has_read: bool,
+    has_write: bool,
+    has_rpc: bool,
+    size: Size,
+}
+
+impl From<NodeMetadata> for Metadata {
+    fn from(metadata: NodeMetadata) -> Self {
+        Self {
+            is_branch: metadata.is_branch,
+            has_read: metadata.has_read,
+            has_write: metadata.has_write,
+            has_rpc: metadata.has_rpc,
+            size: metadata.size,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Written {
+    written: usize,
+}
+
+#[derive(Serialize)]
+struct Dump {
+    offset: Size,
+    data: String,
+}
+
+#[derive(Serialize)]
+struct Created {
+    name: String,
+}
+
+/// Strip the slashes surrounding a path, so that `/os/win/` refers to `os/win`.
+pub fn normalize_path(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+/// Parse a decimal, or `0x` prefixed hexadecimal number.
+pub fn parse_size(value: &str) -> Option<Size> {
+    match value.strip_prefix("0x") {
+        Some(hex) => Size::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// List the entries of a branch.
+pub fn list(frontend: &impl Frontend, path: &str) -> Result<Vec<Entry>> {
+    let mut entries = vec![];
+
+    let cb = &mut |entry: ListEntry| {
+        entries.push(Entry {
+            name: String::from(&*entry.name),
+            is_branch: entry.is_branch,
+        });
+        true
+    };
+
+    frontend.list(normalize_path(path), &mut cb.into())?;
+
+    Ok(entries)
+}
+
+/// Read into `buf` at `offset`, and return the amount of bytes read before the first failure.
+pub fn read_at<F: Frontend>(handle: &ObjHandle<F>, offset: Size, buf: &mut [u8]) -> Result<usize> {
+    let len = buf.len();
+    let mut failed: Option<(Size, Error)> = None;
+
+    {
+        let out_fail = &mut |fail: RWFailData| {
+            let (CTup2(addr, _), err) = fail.into();
+            if failed.is_none_or(|(first, _)| addr < first) {
+                failed = Some((addr, err));
+            }
+            true
+        };
+        let mut out_fail = out_fail.into();
+
+        let inp = &mut core::iter::once(CTup2(offset, buf.into()));
+
+        handle.read(VecOps {
+            inp: inp.into(),
+            out: None,
+            out_fail: Some(&mut out_fail),
+        })?;
+    }
+
+    match failed {
+        Some((addr, _)) if addr > offset => Ok((addr - offset) as usize),
+        Some((_, err)) => Err(err.into()),
+        None => Ok(len),
+    }
+}
+
+/// Write `data` at `offset`, and return the amount of bytes written before the first failure.
+pub fn write_at<F: Frontend>(handle: &ObjHandle<F>, offset: Size, data: &[u8]) -> Result<usize> {
+    let mut failed: Option<(Size, Error)> = None;
+
+    {
+        let out_fail = &mut |fail: ROFailData| {
+            let (CTup2(addr, _), err) = fail.into();
+            if failed.is_none_or(|(first, _)| addr < first) {
+                failed = Some((addr, err));
+            }
+            true
+        };
+        let mut out_fail = out_fail.into();
+
+        let inp = &mut core::iter::once(CTup2(offset, data.into()));
+
+        handle.write(VecOps {
+            inp: inp.into(),
+            out: None,
+            out_fail: Some(&mut out_fail),
+        })?;
+    }
+
+    match failed {
+        Some((addr, _)) if addr > offset => Ok((addr - offset) as usize),
+        Some((_, err)) => Err(err.into()),
+        None => Ok(data.len()),
+    }
+}
+
+/// Print the entries of a branch, with a trailing slash for branches.
+pub fn ls(frontend: &impl Frontend, path: &str, json: bool, out: &mut impl Write) -> Result<()> {
+    let entries = list(frontend, path)?;
+
+    if json {
+        serde_json::to_writer(&mut *out, &entries)?;
+        writeln!(out)?;
+    } else {
+        for entry in entries {
+            let suffix = if entry.is_branch { "/" } else { "" };
+            writeln!(out, "{}{}", entry.name, suffix)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the metadata of a path.
+pub fn stat(frontend: &impl Frontend, path: &str, json: bool, out: &mut impl Write) -> Result<()> {
+    let metadata = Metadata::from(frontend.metadata(normalize_path(path))?);
+
+    if json {
+        serde_json::to_writer(&mut *out, &metadata)?;
+        writeln!(out)?;
+    } else {
+        writeln!(out, "is_branch: {}", metadata.is_branch)?;
+        writeln!(out, "has_read: {}", metadata.has_read)?;
+        writeln!(out, "has_write: {}", metadata.has_write)?;
+        writeln!(out, "has_rpc: {}", metadata.has_rpc)?;
+        writeln!(out, "size: {}", metadata.size)?;
+    }
+
+    Ok(())
+}
+
+/// Copy the raw contents of a leaf, starting at `offset`.
+///
+/// Without a `len`, this reads up to the size of the leaf, or until it can not be read any further
+/// if the size is unknown.
+pub fn cat(
+    frontend: &impl Frontend,
+    path: &str,
+    offset: Size,
+    len: Option<Size>,
+    out: &mut impl Write,
+) -> Result<()> {
+    let path = normalize_path(path);
+
+    let end = match len {
+        Some(len) => Some(offset.saturating_add(len)),
+        None => match frontend.metadata(path)?.size {
+            0 => None,
+            size => Some(size),
+        },
+    };
+
+    let handle = frontend.open_handle(path)?;
+    let mut buf = vec![0; CHUNK_LEN];
+    let mut pos = offset;
+
+    while end.is_none_or(|end| pos < end) {
+        let len = end.map_or(CHUNK_LEN, |end| {
+            core::cmp::min(CHUNK_LEN as Size, end - pos) as usize
+        });
+
+        let read = match read_at(&handle, pos, &mut buf[..len]) {
+            Ok(read) => read,
+            // Failing to read anything at all is an error, afterwards it marks the end
+            Err(e) if pos == offset => return Err(e),
+            Err(_) => break,
+        };
+
+        out.write_all(&buf[..read])?;
+        pos += read as Size;
+
+        if read < len {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write `data` to a leaf at `offset`, and print the amount of bytes written.
+pub fn write(
+    frontend: &impl Frontend,
+    path: &str,
+    offset: Size,
+    data: &[u8],
+    json: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let handle = frontend.open_handle(normalize_path(path))?;
+    let written = write_at(&handle, offset, data)?;
+
+    if json {
+        serde_json::to_writer(&mut *out, &Written { written })?;
+        writeln!(out)?;
+    } else {
+        writeln!(out, "{written}")?;
+    }
+
+    Ok(())
+}
+
+/// Print `len` bytes of a leaf at `offset`, `xxd` style.
+pub fn hexdump(
+    frontend: &impl Frontend,
+    path: &str,
+    offset: Size,
+    len: usize,
+    json: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let handle = frontend.open_handle(normalize_path(path))?;
+
+    let mut data = vec![0; len];
+    let read = read_at(&handle, offset, &mut data)?;
+    data.truncate(read);
+
+    if json {
+        let dump = Dump {
+            offset,
+            data: to_hex(&data),
+        };
+        serde_json::to_writer(&mut *out, &dump)?;
+        writeln!(out)?;
+    } else {
+        write_hexdump(out, offset, &data)?;
+    }
+
+    Ok(())
+}
+
+/// Format `data` located at `offset` as lines of `xxd` style hex dump.
+pub fn write_hexdump(out: &mut impl Write, offset: Size, data: &[u8]) -> std::io::Result<()> {
+    for (i, line) in data.chunks(HEXDUMP_WIDTH).enumerate() {
+        write!(out, "{:08x}:", offset + (i * HEXDUMP_WIDTH) as Size)?;
+
+        for group in 0..(HEXDUMP_WIDTH / 2) {
+            write!(out, " ")?;
+            for idx in (group * 2)..(group * 2 + 2) {
+                match line.get(idx) {
+                    Some(b) => write!(out, "{b:02x}")?,
+                    None => write!(out, "  ")?,
+                }
+            }
+        }
+
+        let ascii = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+
+        writeln!(out, "  {ascii}")?;
+    }
+
+    Ok(())
+}
+
+/// Perform a remote procedure call on a leaf, and print the first `output_len` bytes of output.
+///
+/// In text mode the output is written raw, without its trailing zero bytes.
+pub fn rpc(
+    frontend: &impl Frontend,
+    path: &str,
+    input: &[u8],
+    output_len: usize,
+    json: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let handle = frontend.open_handle(normalize_path(path))?;
+
+    let mut output = vec![0; output_len];
+    handle.rpc(input, &mut output)?;
+
+    if json {
+        #[derive(Serialize)]
+        struct Output {
+            output: String,
+        }
+
+        serde_json::to_writer(
+            &mut *out,
+            &Output {
+                output: to_hex(&output),
+            },
+        )?;
+        writeln!(out)?;
+    } else {
+        let len = output.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        out.write_all(&output[..len])?;
+    }
+
+    Ok(())
+}
+
+/// Create a new instance by writing `spec` to the `new` leaf of `branch`, and print its name.
+///
+/// `spec` takes the form of `<name> [-c chain_on] <os/connector>[:args[:extra args]]`.
+pub fn new_instance(
+    frontend: &impl Frontend,
+    branch: &str,
+    spec: &str,
+    json: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let spec = spec.trim();
+
+    let name = match spec.split_once(' ') {
+        Some((name, _)) => name,
+        None => bail!("instance specification needs a name, and what to create"),
+    };
+
+    let handle = frontend.open_handle(&format!("{}/new", normalize_path(branch)))?;
+    let written = write_at(&handle, 0, spec.as_bytes());
+
+    if !matches!(written, Ok(written) if written == spec.len()) {
+        // Local backends describe what went wrong, which beats a bare error kind
+        let mut reason = vec![];
+        let new_error = format!("{}/new_error", normalize_path(branch));
+
+        if cat(frontend, &new_error, 0, None, &mut reason).is_ok() {
+            // Failures of other writers may be listed along with this one
+            let prefix = format!("{spec}: ");
+            let reason = String::from_utf8_lossy(&reason);
+
+            if let Some(line) = reason.lines().find(|l| l.starts_with(&prefix)) {
+                bail!("{line}");
+            }
+        }
+
+        written?;
+        bail!("failed to create {name}");
+    }
+
+    if json {
+        serde_json::to_writer(
+            &mut *out,
+            &Created {
+                name: name.to_string(),
+            },
+        )?;
+        writeln!(out)?;
+    } else {
+        writeln!(out, "{name}")?;
+    }
+
+    Ok(())
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filer::prelude::v1::Result;
+    use std::sync::Mutex;
+
+    /// In-memory backend with a readable and writeable leaf, and a `connector/new` leaf that
+    /// only accepts a single name, describing its failures in `connector/new_error`.
+    #[derive(Default)]
+    struct BufBackend {
+        data: Mutex<Vec<u8>>,
+    }
+
+    const BUF: usize = 0;
+    const NEW: usize = 1;
+    const NEW_ERROR: usize = 2;
+
+    const NEW_ERRORS: &[u8] = b"kvm kvm: kvm already exists\nvbox vbox: plugin not found\n";
+
+    impl Backend for BufBackend {
+        fn read(&self, _: BackendStack, handle: usize, mut data: VecOps<RWData>) -> Result<()> {
+            let buf = match handle {
+                NEW_ERROR => NEW_ERRORS.to_vec(),
+                _ => self.data.lock().unwrap().clone(),
+            };
+            for CTup2(off, mut to) in data.inp {
+                let (off, len) = (off as usize, to.len());
+                if off + len <= buf.len() {
+                    to.copy_from_slice(&buf[off..(off + len)]);
+                    opt_call(data.out.as_deref_mut(), CTup2(off as Size, to));
+                } else {
+                    let e = Error(ErrorOrigin::Read, ErrorKind::OutOfBounds);
+                    opt_call(
+                        data.out_fail.as_deref_mut(),
+                        (CTup2(off as Size, to), e).into(),
+                    );
+                }
+            }
+            Ok(())
+        }
+
+        fn write(&self, _: BackendStack, handle: usize, mut data: VecOps<ROData>) -> Result<()> {
+            let mut buf = self.data.lock().unwrap();
+            for CTup2(off, from) in data.inp {
+                if handle == NEW {
+                    if !from.starts_with(b"qemu ") {
+                        let e = Error(ErrorOrigin::Backend, ErrorKind::AlreadyExists);
+                        opt_call(data.out_fail.as_deref_mut(), (CTup2(off, from), e).into());
+                    }
+                    continue;
+                }
+                let off = off as usize;
+                if buf.len() < off + from.len() {
+                    buf.resize(off + from.len(), 0);
+                }
+                buf[off..(off + from.len())].copy_from_slice(&from);
+                opt_call(data.out.as_deref_mut(), CTup2(off as Size, from));
+            }
+            Ok(())
+        }
+
+        fn rpc(&self, _: BackendStack, _: usize, input: &[u8], output: &mut [u8]) -> Result<()> {
+            let len = core::cmp::min(input.len(), output.len());
+            output[..len].copy_from_slice(&input[..len]);
+            Ok(())
+        }
+
+        fn close(&self, _: BackendStack, _: usize) -> Result<()> {
+            Ok(())
+        }
+
+        fn open(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<usize> {
+            match path {
+                "buf" => Ok(BUF),
+                "connector/new" => Ok(NEW),
+                "connector/new_error" => Ok(NEW_ERROR),
+                _ => Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+            }
+        }
+
+        fn metadata(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<NodeMetadata> {
+            match path {
+                "" | "connector" => Ok(NodeMetadata::branch()),
+                "buf" => Ok(NodeMetadata {
+                    has_read: true,
+                    has_write: true,
+                    has_rpc: true,
+                    size: self.data.lock().unwrap().len() as Size,
+                    ..Default::default()
+                }),
+                "connector/new" => Ok(NodeMetadata {
+                    has_write: true,
+                    ..Default::default()
+                }),
+                "connector/new_error" => Ok(NodeMetadata {
+                    has_read: true,
+                    size: NEW_ERRORS.len() as Size,
+                    ..Default::default()
+                }),
+                _ => Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+            }
+        }
+
+        fn list(
+            &self,
+            _: BackendStack,
+            path: &str,
+            _: &CPluginStore,
+            out: &mut OpaqueCallback<ListEntry>,
+        ) -> Result<()> {
+            match path {
+                "" => {
+                    let _ = out.call(ListEntry::new("buf".into(), false));
+                    let _ = out.call(ListEntry::new("connector".into(), true));
+                }
+                "connector" => {
+                    let _ = out.call(ListEntry::new("new".into(), false));
+                }
+                _ => return Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+            }
+            Ok(())
+        }
+    }
+
+    pub(crate) fn node() -> CArcSome<Node> {
+        Node::new(BufBackend::default()).into()
+    }
+
+    fn output(func: impl FnOnce(&mut Vec<u8>) -> anyhow::Result<()>) -> String {
+        let mut out = vec![];
+        func(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    pub fn cli_ls_stat() {
+        let node = node();
+
+        assert_eq!(output(|o| ls(&node, "/", false, o)), "buf\nconnector/\n");
+        assert_eq!(
+            output(|o| ls(&node, "/", true, o)),
+            "[{\"name\":\"buf\",\"is_branch\":false},{\"name\":\"connector\",\"is_branch\":true}]\n"
+        );
+
+        assert_eq!(
+            output(|o| stat(&node, "/connector/", true, o)),
+            "{\"is_branch\":true,\"has_read\":false,\"has_write\":false,\"has_rpc\":false,\"size\":0}\n"
+        );
+        assert!(output(|o| stat(&node, "buf", false, o)).contains("has_write: true\n"));
+
+        assert!(ls(&node, "missing", false, &mut vec![]).is_err());
+    }
+
+    #[test]
+    pub fn cli_read_write() {
+        let node = node();
+
+        assert_eq!(
+            output(|o| write(&node, "buf", 4, b"hello", true, o)),
+            "{\"written\":5}\n"
+        );
+
+        assert_eq!(output(|o| cat(&node, "buf", 0, None, o)), "\0\0\0\0hello");
+        assert_eq!(output(|o| cat(&node, "buf", 5, Some(3), o)), "ell");
+
+        assert_eq!(
+            output(|o| hexdump(&node, "buf", 0, 9, false, o)),
+            "00000000: 0000 0000 6865 6c6c 6f                   ....hello\n"
+        );
+        assert_eq!(
+            output(|o| hexdump(&node, "buf", 4, 5, true, o)),
+            "{\"offset\":4,\"data\":\"68656c6c6f\"}\n"
+        );
+
+        // Reading past the end fails as a whole
+        assert!(hexdump(&node, "buf", 0, 16, false, &mut vec![]).is_err());
+
+        assert_eq!(output(|o| rpc(&node, "buf", b"ping", 8, false, o)), "ping");
+        assert_eq!(
+            output(|o| rpc(&node, "buf", b"ping", 6, true, o)),
+            "{\"output\":\"70696e670000\"}\n"
+        );
+    }
+
+    #[test]
+    pub fn cli_new_instance() {
+        let node = node();
+
+        assert_eq!(
+            output(|o| new_instance(&node, "connector", "qemu qemu:my-vm", false, o)),
+            "qemu\n"
+        );
+
+        let err = new_instance(&node, "connector", "kvm kvm", false, &mut vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "kvm kvm: kvm already exists");
+
+        // Without a description of its own, the failure is only reported by its kind
+        let err = new_instance(&node, "connector", "kvm kvm:x", false, &mut vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "backend: already exists");
+
+        assert!(new_instance(&node, "connector", "qemu", false, &mut vec![]).is_err());
+    }
+
+    #[test]
+    pub fn cli_parse_size() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("0x1000"), Some(4096));
+        assert_eq!(parse_size("0xg"), None);
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::{anyhow, Result};
+use clap::*;
+
+use cloudflow_cli::config::{connect, ClientConfig, DEFAULT_CONFIG_PATH};
+use cloudflow_cli::*;
+
+use filer::prelude::v1::*;
+
+use log::*;
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+fn main() -> Result<()> {
+    let matches = parse_args();
+
+    let level = match matches.get_count("verbose") {
+        0 => Level::Error,
+        1 => Level::Warn,
+        2 => Level::Info,
+        3 => Level::Debug,
+        _ => Level::Trace,
+    };
+
+    // Keep stdout clean for the actual output
+    simplelog::TermLogger::init(
+        level.to_level_filter(),
+        simplelog::Config::default(),
+        simplelog::TerminalMode::Stderr,
+        simplelog::ColorChoice::Auto,
+    )
+    .unwrap();
+
+    let config = match matches.get_one::<String>("config") {
+        Some(path) => ClientConfig::load(path)?,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => ClientConfig::load(DEFAULT_CONFIG_PATH)?,
+        None => ClientConfig::default(),
+    };
+
+    let json = matches.get_flag("json");
+    let mut out = std::io::stdout().lock();
+
+    let addr = matches.get_one::<String>("addr");
+
+    match matches.get_flag("remote") || addr.is_some() {
+        true => {
+            let addr = addr
+                .or(config.socket_addr.as_ref())
+                .ok_or_else(|| anyhow!("no socket_addr configured to connect to"))?;
+            let client = connect(addr, &config.credentials()?)?;
+            run(&client, &matches, json, &mut out)?;
+        }
+        false => {
+            let node = cloudflow::create_node();
+            cloudflow_minidump::on_node(&node, Default::default());
+            run(&node, &matches, json, &mut out)?;
+        }
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
+
+fn run(
+    frontend: &impl Frontend,
+    matches: &ArgMatches,
+    json: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let path = |m: &ArgMatches| m.get_one::<String>("path").cloned().unwrap_or_default();
+    let offset = |m: &ArgMatches| m.get_one::<Size>("offset").copied().unwrap_or(0);
+
+    match matches.subcommand() {
+        Some(("ls", m)) => ls(frontend, &path(m), json, out),
+        Some(("stat", m)) => stat(frontend, &path(m), json, out),
+        Some(("cat", m)) => cat(
+            frontend,
+            &path(m),
+            offset(m),
+            m.get_one::<Size>("length").copied(),
+            out,
+        ),
+        Some(("write", m)) => {
+            let data = input(m, "data")?;
+            write(frontend, &path(m), offset(m), &data, json, out)
+        }
+        Some(("hexdump", m)) => {
+            let len = *m.get_one::<Size>("length").unwrap() as usize;
+            hexdump(frontend, &path(m), offset(m), len, json, out)
+        }
+        Some(("rpc", m)) => {
+            let data = input(m, "input")?;
+            let output_len = *m.get_one::<Size>("output-len").unwrap() as usize;
+            rpc(frontend, &path(m), &data, output_len, json, out)
+        }
+        Some(("new-connector", m)) => new_instance(frontend, "connector", &spec(m), json, out),
+        Some(("new-os", m)) => new_instance(frontend, "os", &spec(m), json, out),
+        Some(("shell", _)) => shell::run(frontend),
+        _ => unreachable!(),
+    }
+}
+
+/// Data given as an argument, or read from stdin if it is missing.
+fn input(matches: &ArgMatches, name: &str) -> Result<Vec<u8>> {
+    match matches.get_one::<String>(name) {
+        Some(data) => Ok(data.as_bytes().to_vec()),
+        None => {
+            let mut data = vec![];
+            std::io::stdin().read_to_end(&mut data)?;
+            Ok(data)
+        }
+    }
+}
+
+fn spec(matches: &ArgMatches) -> String {
+    matches
+        .get_many::<String>("spec")
+        .unwrap()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn size_arg(name: &'static str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .action(ArgAction::Set)
+        .value_parser(|v: &str| parse_size(v).ok_or("expected a decimal or 0x prefixed number"))
+}
+
+fn path_arg() -> Arg {
+    Arg::new("path").required(true)
+}
+
+fn spec_arg() -> Arg {
+    Arg::new("spec")
+        .required(true)
+        .num_args(1..)
+        .trailing_var_arg(true)
+        .allow_hyphen_values(true)
+        .help("<name> [-c chain_on] <os/connector>[:args[:extra args]]")
+}
+
+fn parse_args() -> ArgMatches {
+    Command::new("cloudflow-cli")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .subcommand_required(true)
+        .arg(Arg::new("verbose").short('v').action(ArgAction::Count))
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .short('j')
+                .action(ArgAction::SetTrue)
+                .help("Print machine-readable JSON"),
+        )
+        .arg(
+            Arg::new("remote")
+                .long("remote")
+                .short('r')
+                .action(ArgAction::SetTrue)
+                .help("Connect to a remote node instead of creating one in process"),
+        )
+        .arg(
+            Arg::new("addr")
+                .long("addr")
+                .short('a')
+                .action(ArgAction::Set)
+                .help("Address of the remote node [default: the configured socket_addr]"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .short('c')
+                .action(ArgAction::Set)
+                .help("Client configuration [default: /etc/memflow/client.conf]"),
+        )
+        .subcommand(
+            Command::new("ls")
+                .about("List entries of a branch")
+                .arg(Arg::new("path")),
+        )
+        .subcommand(
+            Command::new("stat")
+                .about("Show metadata of a path")
+                .arg(path_arg()),
+        )
+        .subcommand(
+            Command::new("cat")
+                .about("Copy the raw contents of a leaf to stdout")
+                .arg(path_arg())
+                .arg(size_arg("offset"))
+                .arg(size_arg("length")),
+        )
+        .subcommand(
+            Command::new("write")
+                .about("Write data, or stdin, to a leaf")
+                .arg(path_arg())
+                .arg(Arg::new("data"))
+                .arg(size_arg("offset")),
+        )
+        .subcommand(
+            Command::new("hexdump")
+                .about("Show the contents of a leaf as a hex dump")
+                .arg(path_arg())
+                .arg(size_arg("offset"))
+                .arg(size_arg("length").default_value("256")),
+        )
+        .subcommand(
+            Command::new("rpc")
+                .about("Perform a remote procedure call on a leaf")
+                .arg(path_arg())
+                .arg(Arg::new("input"))
+                .arg(size_arg("output-len").default_value("4096")),
+        )
+        .subcommand(
+            Command::new("new-connector")
+                .about("Create a new connector instance")
+                .arg(spec_arg()),
+        )
+        .subcommand(
+            Command::new("new-os")
+                .about("Create a new OS instance")
+                .arg(spec_arg()),
+        )
+        .subcommand(Command::new("shell").about("Explore the node interactively"))
+        .get_matches()
+}
@@ -0,0 +1,370 @@
+//! Interactive shell for exploring the node tree.
+//!
+//! Paths are relative to the current branch, which is changed with `cd`. Tab completes command
+//! names and paths, and the history is kept in `~/.cloudflow_history`.
+
+use crate::*;
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use std::io;
+use std::path::PathBuf;
+
+/// Bytes shown by `xxd` without an explicit length.
+const DEFAULT_XXD_LEN: usize = 256;
+
+/// Commands, along with their usage and description.
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("cd", "[path]", "change the current branch"),
+    ("pwd", "", "print the current branch"),
+    ("ls", "[path]", "list entries of a branch"),
+    ("stat", "<path>", "show metadata of a path"),
+    ("cat", "<path>", "print the contents of a leaf"),
+    ("xxd", "<path> [addr] [len]", "hex dump of a leaf"),
+    ("help", "", "show this message"),
+    ("exit", "", "leave the shell"),
+];
+
+/// Writer passing everything through, while remembering the last byte written.
+struct LastByte<'a, W> {
+    out: &'a mut W,
+    last: Option<u8>,
+}
+
+impl<W: Write> Write for LastByte<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.out.write(buf)?;
+        if written > 0 {
+            self.last = Some(buf[written - 1]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// State of a shell session on top of a frontend.
+pub struct Shell<'a, F> {
+    frontend: &'a F,
+    /// Current branch, in the form of a node path.
+    cwd: String,
+}
+
+impl<'a, F: Frontend> Shell<'a, F> {
+    pub fn new(frontend: &'a F) -> Self {
+        Self {
+            frontend,
+            cwd: String::new(),
+        }
+    }
+
+    /// Turn a path relative to the current branch, or an absolute one, into a node path.
+    pub fn resolve(&self, path: &str) -> String {
+        resolve(&self.cwd, path)
+    }
+
+    /// Execute a single line of input. Returns `false` once the shell should be left.
+    pub fn execute(&mut self, line: &str, out: &mut impl Write) -> Result<bool> {
+        let mut args = line.split_whitespace();
+
+        let cmd = match args.next() {
+            Some(cmd) => cmd,
+            None => return Ok(true),
+        };
+
+        let args = args.collect::<Vec<_>>();
+        let path = |idx: usize| self.resolve(args.get(idx).copied().unwrap_or(""));
+        let number = |idx: usize| -> Result<Option<Size>> {
+            match args.get(idx) {
+                Some(arg) => match parse_size(arg) {
+                    Some(num) => Ok(Some(num)),
+                    None => bail!("{arg} is not a number"),
+                },
+                None => Ok(None),
+            }
+        };
+
+        match cmd {
+            "cd" => {
+                let path = path(0);
+                if !self.frontend.metadata(&path)?.is_branch {
+                    bail!("/{path} is not a branch");
+                }
+                self.cwd = path;
+            }
+            "pwd" => writeln!(out, "/{}", self.cwd)?,
+            "ls" => ls(self.frontend, &path(0), false, out)?,
+            "stat" | "cat" | "xxd" if args.is_empty() => bail!("{cmd} needs a path"),
+            "stat" => stat(self.frontend, &path(0), false, out)?,
+            "cat" => {
+                // Leaves such as physical memory are far too large to be buffered
+                let mut out = LastByte { out, last: None };
+                cat(self.frontend, &path(0), 0, None, &mut out)?;
+                if out.last.is_some_and(|last| last != b'\n') {
+                    writeln!(out.out)?;
+                }
+            }
+            "xxd" => {
+                let addr = number(1)?.unwrap_or(0);
+                let len = number(2)?.map_or(DEFAULT_XXD_LEN, |len| len as usize);
+                hexdump(self.frontend, &path(0), addr, len, false, out)?;
+            }
+            "help" => {
+                for (cmd, usage, description) in COMMANDS {
+                    writeln!(out, "{:<24}{description}", format!("{cmd} {usage}"))?;
+                }
+            }
+            "exit" | "quit" => return Ok(false),
+            _ => bail!("unknown command {cmd}, try help"),
+        }
+
+        Ok(true)
+    }
+
+    /// Completion candidates for the word ending at `pos`, and where that word starts.
+    ///
+    /// The first word is completed as a command, all others as paths.
+    pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<Pair>) {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..];
+
+        if line[..start].trim().is_empty() {
+            let candidates = COMMANDS
+                .iter()
+                .filter(|(cmd, _, _)| cmd.starts_with(word))
+                .map(|(cmd, _, _)| Pair {
+                    display: cmd.to_string(),
+                    replacement: format!("{cmd} "),
+                })
+                .collect();
+            return (start, candidates);
+        }
+
+        // Only the last component of the path gets replaced
+        let (branch, prefix, start) = match word.rfind('/') {
+            Some(idx) => (&word[..=idx], &word[(idx + 1)..], start + idx + 1),
+            None => ("", word, start),
+        };
+
+        let entries = list(self.frontend, &self.resolve(branch)).unwrap_or_default();
+
+        let candidates = entries
+            .into_iter()
+            .filter(|entry| entry.name.starts_with(prefix))
+            .map(|entry| {
+                let replacement = if entry.is_branch {
+                    format!("{}/", entry.name)
+                } else {
+                    format!("{} ", entry.name)
+                };
+                Pair {
+                    display: entry.name,
+                    replacement,
+                }
+            })
+            .collect();
+
+        (start, candidates)
+    }
+}
+
+fn resolve(cwd: &str, path: &str) -> String {
+    let mut components = if path.starts_with('/') {
+        vec![]
+    } else {
+        cwd.split('/').filter(|c| !c.is_empty()).collect()
+    };
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+
+    components.join("/")
+}
+
+/// Completes through the shell, which gets updated after every command.
+struct ShellHelper<'a, F> {
+    shell: Shell<'a, F>,
+}
+
+impl<F: Frontend> Completer for ShellHelper<'_, F> {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(self.shell.complete(line, pos))
+    }
+}
+
+impl<F> Hinter for ShellHelper<'_, F> {
+    type Hint = String;
+}
+
+impl<F> Highlighter for ShellHelper<'_, F> {}
+
+impl<F> Validator for ShellHelper<'_, F> {}
+
+impl<F: Frontend> Helper for ShellHelper<'_, F> {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cloudflow_history"))
+}
+
+/// Run the shell on the terminal until it is exited, or the input ends.
+pub fn run(frontend: &impl Frontend) -> Result<()> {
+    let mut editor = Editor::<ShellHelper<_>, DefaultHistory>::new()?;
+    editor.set_helper(Some(ShellHelper {
+        shell: Shell::new(frontend),
+    }));
+
+    let history = history_path();
+    if let Some(history) = &history {
+        // There is no history on the first run
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let prompt = format!("cloudflow:/{}> ", editor.helper().unwrap().shell.cwd);
+
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            // Ctrl-C discards the current line
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+
+        let shell = &mut editor.helper_mut().unwrap().shell;
+        let mut out = std::io::stdout().lock();
+
+        match shell.execute(&line, &mut out) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => eprintln!("error: {e}"),
+        }
+
+        out.flush()?;
+    }
+
+    if let Some(history) = &history {
+        if let Err(e) = editor.save_history(history) {
+            log::warn!("unable to save history to {}: {e}", history.display());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::node;
+
+    fn execute(shell: &mut Shell<CArcSome<Node>>, line: &str) -> anyhow::Result<String> {
+        let mut out = vec![];
+        shell.execute(line, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn replacements(candidates: Vec<Pair>) -> Vec<String> {
+        candidates.into_iter().map(|c| c.replacement).collect()
+    }
+
+    #[test]
+    pub fn shell_resolve() {
+        assert_eq!(resolve("", "os/win"), "os/win");
+        assert_eq!(resolve("os/win", "processes/../modules/"), "os/win/modules");
+        assert_eq!(resolve("os/win", "/connector"), "connector");
+        assert_eq!(resolve("os", "../../.."), "");
+    }
+
+    #[test]
+    pub fn shell_commands() {
+        let node = node();
+        let mut shell = Shell::new(&node);
+
+        assert_eq!(execute(&mut shell, "cd connector").unwrap(), "");
+        assert_eq!(execute(&mut shell, "pwd").unwrap(), "/connector\n");
+        assert_eq!(execute(&mut shell, "ls").unwrap(), "new\n");
+        assert_eq!(execute(&mut shell, "ls ..").unwrap(), "buf\nconnector/\n");
+
+        assert!(execute(&mut shell, "cd ../buf").is_err());
+        assert!(execute(&mut shell, "cd missing").is_err());
+        assert_eq!(execute(&mut shell, "pwd").unwrap(), "/connector\n");
+
+        assert!(execute(&mut shell, "bogus").is_err());
+        assert!(execute(&mut shell, "xxd").is_err());
+
+        assert_eq!(execute(&mut shell, "cd").unwrap(), "");
+        assert_eq!(execute(&mut shell, "  ").unwrap(), "");
+        assert!(!shell.execute("exit", &mut vec![]).unwrap());
+    }
+
+    #[test]
+    pub fn shell_read() {
+        let node = node();
+        let mut shell = Shell::new(&node);
+
+        write(&node, "buf", 0, b"hello", false, &mut vec![]).unwrap();
+
+        assert_eq!(execute(&mut shell, "cat buf").unwrap(), "hello\n");
+
+        write(&node, "buf", 5, b"\n", false, &mut vec![]).unwrap();
+        assert_eq!(execute(&mut shell, "cat buf").unwrap(), "hello\n");
+        assert_eq!(
+            execute(&mut shell, "xxd /buf 0x1 3").unwrap(),
+            "00000001: 656c 6c                                  ell\n"
+        );
+        assert!(execute(&mut shell, "xxd buf one").is_err());
+    }
+
+    #[test]
+    pub fn shell_complete() {
+        let node = node();
+        let mut shell = Shell::new(&node);
+
+        let (start, candidates) = shell.complete("c", 1);
+        assert_eq!(start, 0);
+        assert_eq!(replacements(candidates), ["cd ", "cat "]);
+
+        let (start, candidates) = shell.complete("ls c", 4);
+        assert_eq!(start, 3);
+        assert_eq!(replacements(candidates), ["connector/"]);
+
+        let (start, candidates) = shell.complete("ls /connector/n", 15);
+        assert_eq!(start, 14);
+        assert_eq!(replacements(candidates), ["new "]);
+
+        execute(&mut shell, "cd connector").unwrap();
+        let (start, candidates) = shell.complete("cat ../b", 8);
+        assert_eq!(start, 7);
+        assert_eq!(replacements(candidates), ["buf "]);
+
+        // Only the text before the cursor counts
+        let (_, candidates) = shell.complete("ls x", 3);
+        assert_eq!(replacements(candidates), ["new "]);
+    }
+}
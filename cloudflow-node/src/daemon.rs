@@ -0,0 +1,304 @@
+//! Daemon mode, configured through `daemon.conf`.
+
+use anyhow::{Context, Result};
+
+use cloudflow::config::InstancesConfig;
+
+use filer::prelude::v1::*;
+use filer_http::HttpServer;
+use filer_tokio::{FilerServer, Listener, NodeAddr, TokenAuth};
+
+use log::*;
+use serde::Deserialize;
+
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+/// Where the configuration is installed to.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/memflow/daemon.conf";
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// Maximum level of messages logged, such as `info` or `debug`.
+    pub verbosity: String,
+    /// File to write the process ID to while the daemon is running.
+    pub pid_file: Option<PathBuf>,
+    /// File to append the log to, instead of stdout.
+    pub log_file: Option<PathBuf>,
+    /// Address to serve the node on.
+    ///
+    /// This is either a TCP address, optionally prefixed by `tcp://`, or the path of a unix domain
+    /// socket prefixed by `unix:`.
+    pub socket_addr: Option<String>,
+    /// TCP address to additionally serve the node on over HTTP, see `filer_http`.
+    ///
+    /// The HTTP gateway does not authenticate clients, regardless of `auth_key_file`.
+    pub http_addr: Option<String>,
+    /// Key file with the tokens clients may authenticate with. Without one, every client is
+    /// accepted.
+    pub auth_key_file: Option<PathBuf>,
+    /// Where to mount the node with FUSE, if anywhere.
+    pub fuse_mount: Option<String>,
+    pub fuse_uid: Option<u32>,
+    pub fuse_gid: Option<u32>,
+    /// Connector and OS instances to create at startup.
+    pub instances: InstancesConfig,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            verbosity: "info".into(),
+            pid_file: None,
+            log_file: None,
+            socket_addr: None,
+            http_addr: None,
+            auth_key_file: None,
+            fuse_mount: None,
+            fuse_uid: None,
+            fuse_gid: None,
+            instances: Default::default(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        Self::parse(&config).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    pub fn parse(config: &str) -> Result<Self> {
+        let config: Self = serde_json::from_str(config)?;
+        config.level_filter()?;
+        Ok(config)
+    }
+
+    pub fn level_filter(&self) -> Result<LevelFilter> {
+        LevelFilter::from_str(&self.verbosity)
+            .with_context(|| format!("unknown verbosity {}", self.verbosity))
+    }
+
+    /// Log to the log file, or to stdout without one.
+    pub fn init_logger(&self) -> Result<()> {
+        let level = self.level_filter()?;
+
+        match &self.log_file {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("unable to open {}", path.display()))?;
+                simplelog::WriteLogger::init(level, simplelog::Config::default(), file)?;
+            }
+            None => simplelog::TermLogger::init(
+                level,
+                simplelog::Config::default(),
+                simplelog::TerminalMode::Stdout,
+                simplelog::ColorChoice::Auto,
+            )?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Process ID file, which is removed once dropped.
+pub struct PidFile(PathBuf);
+
+impl PidFile {
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        std::fs::write(&path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("unable to write {}", path.display()))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("unable to remove {}: {e}", self.0.display());
+        }
+    }
+}
+
+/// Serve the node on the configured socket and HTTP addresses until `shutdown` completes.
+///
+/// Failing to serve on either of them stops both. Once this returns, the handles opened by clients
+/// are closed.
+pub async fn serve(
+    config: &DaemonConfig,
+    node: CArcSome<Node>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let auth = match &config.auth_key_file {
+        Some(path) => Some(
+            TokenAuth::from_key_file(path)
+                .with_context(|| format!("unable to read keys from {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let http_listener = match &config.http_addr {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("unable to listen on {addr}"))?;
+            info!("serving the node over HTTP on {addr}");
+            warn!("the HTTP gateway accepts every client");
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let (stop, _) = watch::channel(false);
+    let stopped = || {
+        let mut stopped = stop.subscribe();
+        async move {
+            let _ = stopped.wait_for(|stopped| *stopped).await;
+        }
+    };
+
+    let (served, served_http, ()) = tokio::join!(
+        async {
+            let ret = serve_socket(config, node.clone(), auth, stopped()).await;
+            stop.send_replace(true);
+            ret
+        },
+        async {
+            let ret = match http_listener {
+                Some(listener) => HttpServer::new(listener, node.clone())
+                    .run_until(stopped())
+                    .await
+                    .context("unable to serve the node over HTTP"),
+                None => {
+                    stopped().await;
+                    Ok(())
+                }
+            };
+            stop.send_replace(true);
+            ret
+        },
+        async {
+            tokio::select! {
+                _ = shutdown => {}
+                _ = stopped() => {}
+            }
+            stop.send_replace(true);
+        },
+    );
+
+    served.and(served_http)
+}
+
+/// Serve the node on the socket address until `shutdown` completes.
+async fn serve_socket(
+    config: &DaemonConfig,
+    node: CArcSome<Node>,
+    auth: Option<TokenAuth>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let addr = match &config.socket_addr {
+        Some(addr) => addr,
+        None => {
+            info!("no socket_addr configured, not serving the node");
+            shutdown.await;
+            return Ok(());
+        }
+    };
+
+    match NodeAddr::parse(addr)? {
+        NodeAddr::Tcp(tcp_addr) => {
+            let listener = TcpListener::bind(tcp_addr)
+                .await
+                .with_context(|| format!("unable to listen on {addr}"))?;
+            info!("serving the node on {addr}");
+            run_server(listener, node, auth, shutdown).await
+        }
+        #[cfg(unix)]
+        NodeAddr::Unix(path) => {
+            // A socket left behind by a previous run would fail binding
+            let _ = std::fs::remove_file(path);
+            let listener = tokio::net::UnixListener::bind(path)
+                .with_context(|| format!("unable to listen on {addr}"))?;
+            info!("serving the node on {addr}");
+            let ret = run_server(listener, node, auth, shutdown).await;
+            let _ = std::fs::remove_file(path);
+            ret
+        }
+        #[cfg(not(unix))]
+        NodeAddr::Unix(_) => {
+            anyhow::bail!("unix domain sockets are not supported on this platform")
+        }
+    }
+}
+
+async fn run_server<T: Listener>(
+    listener: T,
+    node: CArcSome<Node>,
+    auth: Option<TokenAuth>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let server = FilerServer::new(listener, node);
+
+    let server = match auth {
+        Some(auth) => server.with_authenticator(auth),
+        None => {
+            warn!("no auth_key_file configured, every client is accepted");
+            server
+        }
+    };
+
+    Ok(server.run_until(shutdown).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parse_shipped_config() {
+        let config = DaemonConfig::parse(include_str!("../../daemon.conf")).unwrap();
+        assert_eq!(config.level_filter().unwrap(), LevelFilter::Info);
+        assert_eq!(config.socket_addr.as_deref(), Some("127.0.0.1:8000"));
+        assert!(config.http_addr.is_none());
+        assert!(config.auth_key_file.is_none());
+        assert!(config.fuse_mount.is_none());
+        assert!(config.instances.connector.is_empty());
+        assert!(config.instances.state_file.is_none());
+    }
+
+    #[test]
+    pub fn parse_partial_config() {
+        let config = DaemonConfig::parse(r#"{"verbosity": "debug"}"#).unwrap();
+        assert_eq!(config.level_filter().unwrap(), LevelFilter::Debug);
+        assert!(config.pid_file.is_none());
+
+        let config = DaemonConfig::parse(
+            r#"{"instances": {
+                "connector": [{"name": "vm", "plugin": "qemu", "args": "my-vm"}],
+                "os": [{"name": "win", "plugin": "win32", "chain_with": "vm", "args": ":arch=x64"}]
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(config.instances.connector[0].build_input(), "qemu:my-vm");
+        assert_eq!(
+            config.instances.os[0].build_input(),
+            "-c vm win32::arch=x64"
+        );
+
+        assert!(DaemonConfig::parse(r#"{"instances": {"os": [{"name": "win"}]}}"#).is_err());
+        assert!(DaemonConfig::parse(r#"{"verbosity": "loud"}"#).is_err());
+        assert!(DaemonConfig::parse(r#"{"socket_addr": 8000}"#).is_err());
+    }
+}
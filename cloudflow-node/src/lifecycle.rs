@@ -0,0 +1,54 @@
+//! Waiting for termination, and tearing the node down afterwards.
+
+use anyhow::Result;
+
+use cloudflow::MemflowBackend;
+use filer_fuse::FuseMount;
+
+use log::*;
+
+use std::future::Future;
+
+/// Completes once the process is asked to terminate, by SIGINT or SIGTERM.
+///
+/// This has to be called within a tokio runtime.
+pub fn termination() -> Result<impl Future<Output = ()>> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        Ok(async move {
+            tokio::select! {
+                _ = interrupt.recv() => info!("received SIGINT, shutting down"),
+                _ = terminate.recv() => info!("received SIGTERM, shutting down"),
+            }
+        })
+    }
+
+    #[cfg(not(unix))]
+    Ok(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("received Ctrl-C, shutting down");
+        }
+    })
+}
+
+/// Unmount FUSE and drop all connector and OS instances of the node.
+///
+/// The instances are dropped even if unmounting fails.
+pub fn shutdown(memflow: &MemflowBackend, fuse: Option<FuseMount>) -> Result<()> {
+    // Closes the handles left open through the mount as well
+    let unmounted = match fuse {
+        Some(fuse) => fuse.unmount(),
+        None => Ok(()),
+    };
+
+    // The backend references itself through the context of the instances' builder, so they
+    // would never be dropped along with the node.
+    memflow.drop_instances();
+
+    Ok(unmounted?)
+}
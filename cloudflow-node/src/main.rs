@@ -1,9 +1,19 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::*;
-use cloudflow::*;
+use cloudflow::config::InstancesConfig;
+use cloudflow::MemflowBackend;
+
+use filer::prelude::v1::*;
 
 use log::*;
 
+use std::sync::Arc;
+
+mod daemon;
+use daemon::{DaemonConfig, PidFile};
+
+mod lifecycle;
+
 fn main() -> Result<()> {
     let args = parse_args();
     let (mount_path, fuse_uid, fuse_gid, elevate, level) = extract_args(&args)?;
@@ -13,6 +23,14 @@ fn main() -> Result<()> {
         info!("Elevated privileges!");
     }
 
+    if args.get_flag("daemon") || args.contains_id("config") {
+        let config = args
+            .get_one::<String>("config")
+            .map(String::as_str)
+            .unwrap_or(daemon::DEFAULT_CONFIG_PATH);
+        return run_daemon(DaemonConfig::load(config)?, mount_path, fuse_uid, fuse_gid);
+    }
+
     simplelog::TermLogger::init(
         level.to_level_filter(),
         simplelog::Config::default(),
@@ -21,26 +39,102 @@ fn main() -> Result<()> {
     )
     .unwrap();
 
-    let node = create_node();
+    let instances = match args.get_one::<String>("instances") {
+        Some(path) => {
+            let config =
+                std::fs::read_to_string(path).with_context(|| format!("unable to read {path}"))?;
+            serde_json::from_str(&config).with_context(|| format!("invalid {path}"))?
+        }
+        None => InstancesConfig::default(),
+    };
 
-    // Add custom plugin
-    cloudflow_minidump::on_node(&node, Default::default());
+    let (node, memflow) = create_node(&instances);
 
-    if let Some(mount_path) = mount_path {
-        println!("Mounting FUSE filesystem on {}", mount_path);
-        std::fs::create_dir_all(mount_path)?;
-        filer_fuse::mount(
-            node,
-            mount_path,
-            sudo::check() == sudo::RunningAs::Root,
-            fuse_uid.unwrap_or(0),
-            fuse_gid.unwrap_or(0),
-        )?;
-    }
+    let fuse = match mount_path {
+        Some(mount_path) => {
+            println!("Mounting FUSE filesystem on {}", mount_path);
+            std::fs::create_dir_all(mount_path)?;
+            Some(filer_fuse::mount(
+                node.clone(),
+                mount_path,
+                sudo::check() == sudo::RunningAs::Root,
+                fuse_uid.unwrap_or(0),
+                fuse_gid.unwrap_or(0),
+            )?)
+        }
+        None => None,
+    };
 
     println!("Initialized!");
 
-    loop {}
+    let runtime = tokio::runtime::Runtime::new()?;
+    let termination = {
+        let _guard = runtime.enter();
+        lifecycle::termination()?
+    };
+    runtime.block_on(termination);
+
+    lifecycle::shutdown(&memflow, fuse)
+}
+
+/// Run as configured, with FUSE options on the command line taking precedence.
+fn run_daemon(
+    config: DaemonConfig,
+    mount_path: Option<&str>,
+    fuse_uid: Option<u32>,
+    fuse_gid: Option<u32>,
+) -> Result<()> {
+    config.init_logger()?;
+
+    let _pid_file = config.pid_file.as_ref().map(PidFile::create).transpose()?;
+
+    let (node, memflow) = create_node(&config.instances);
+
+    let fuse = match mount_path.or(config.fuse_mount.as_deref()) {
+        Some(mount_path) => {
+            info!("Mounting FUSE filesystem on {}", mount_path);
+            std::fs::create_dir_all(mount_path)?;
+            Some(filer_fuse::mount(
+                node.clone(),
+                mount_path,
+                sudo::check() == sudo::RunningAs::Root,
+                fuse_uid.or(config.fuse_uid).unwrap_or(0),
+                fuse_gid.or(config.fuse_gid).unwrap_or(0),
+            )?)
+        }
+        None => None,
+    };
+
+    info!("Daemon initialized with pid {}", std::process::id());
+
+    let served = tokio::runtime::Runtime::new()?.block_on(async {
+        let termination = lifecycle::termination()?;
+        daemon::serve(&config, node.clone(), termination).await
+    });
+
+    if let Err(e) = &served {
+        error!("unable to serve the node: {e:#}");
+    }
+
+    // Tear down in any case, but still report failing to serve
+    lifecycle::shutdown(&memflow, fuse)?;
+    info!("Shut down");
+
+    served
+}
+
+/// Create the node along with the configured instances, logging the ones that failed.
+fn create_node(instances: &InstancesConfig) -> (CArcSome<Node>, Arc<MemflowBackend>) {
+    let (node, memflow, errors) = cloudflow::create_node_with(instances);
+
+    for e in errors {
+        error!("{e}");
+    }
+
+    // Add custom plugin
+    cloudflow_minidump::on_node(&node, Default::default());
+
+    (node, memflow)
 }
 
 fn parse_args() -> ArgMatches {
@@ -76,6 +170,30 @@ fn parse_args() -> ArgMatches {
                 .action(ArgAction::Set)
                 .required(false),
         )
+        .arg(
+            Arg::new("instances")
+                .long("instances")
+                .short('i')
+                .action(ArgAction::Set)
+                .required(false)
+                .help("JSON file with connectors and OSes to create at startup"),
+        )
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .short('d')
+                .action(ArgAction::SetTrue)
+                .required(false)
+                .help("Run as a daemon, as configured in the daemon configuration"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .short('c')
+                .action(ArgAction::Set)
+                .required(false)
+                .help("Daemon configuration, implies --daemon [default: /etc/memflow/daemon.conf]"),
+        )
         .arg(
             Arg::new("elevate")
                 .long("elevate")
@@ -0,0 +1,391 @@
+//! Connector and OS instances to create at startup.
+
+use crate::connector::ConnectorRoot;
+use crate::deps::Kind;
+use crate::os::OsRoot;
+use crate::state::Record;
+use crate::util::split_args;
+use crate::MemflowBackend;
+
+use cglue::arc::CArc;
+use filer::prelude::v1::*;
+use serde::Deserialize;
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Instances to create, keyed by the branch they are created in.
+///
+/// Instances may be listed in any order, they get created once the instance they are chained
+/// with exists.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct InstancesConfig {
+    pub connector: Vec<InstanceConfig>,
+    pub os: Vec<InstanceConfig>,
+    /// File recording the instances created through `new`, which are restored at startup.
+    pub state_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InstanceConfig {
+    pub name: String,
+    /// Plugin creating the instance, such as `qemu` or `win32`.
+    pub plugin: String,
+    /// Instance to chain with, which is an OS for connectors, and a connector for OSes.
+    #[serde(default)]
+    pub chain_with: Option<String>,
+    /// Arguments of the plugin, optionally followed by `:` and extra arguments.
+    #[serde(default)]
+    pub args: String,
+}
+
+impl InstanceConfig {
+    /// Input for `StrBuild::build`, the same as written to `new` after the name.
+    pub fn build_input(&self) -> String {
+        let chain_with = self
+            .chain_with
+            .as_ref()
+            .map(|cw| format!("-c {cw} "))
+            .unwrap_or_default();
+
+        format!("{chain_with}{}:{}", self.plugin, self.args)
+    }
+}
+
+/// Instance that could not be created.
+#[derive(Debug)]
+pub enum InstanceError {
+    /// Building the instance failed, or its name is taken.
+    Build { path: String, error: BuildError },
+    /// The instance to chain with was never created.
+    MissingParent { path: String, parent: String },
+    /// The state file could not be read, so neither are its instances restored, nor are new
+    /// instances recorded.
+    State { path: PathBuf, error: io::Error },
+}
+
+impl fmt::Display for InstanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Build { path, error } => write!(f, "unable to create {path}: {error}"),
+            Self::MissingParent { path, parent } => {
+                write!(f, "unable to create {path}: {parent} does not exist")
+            }
+            Self::State { path, error } => {
+                write!(f, "unable to read state from {}: {error}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for InstanceError {}
+
+impl MemflowBackend {
+    fn exists(&self, kind: Kind, name: &str) -> bool {
+        match kind {
+            Kind::Connector => self.connector.get(name).is_some(),
+            Kind::Os => self.os.get(name).is_some(),
+        }
+    }
+
+    fn build_instance(self: &Arc<Self>, kind: Kind, name: &str, input: &str) -> BuildResult<()> {
+        let ctx = CArc::from(self.clone());
+        let _lock = self.chains.lock();
+
+        match kind {
+            Kind::Connector => self
+                .connector
+                .checked_insert(name, ConnectorRoot::build(input, &ctx)?)?,
+            Kind::Os => self.os.checked_insert(name, OsRoot::build(input, &ctx)?)?,
+        }
+
+        self.chains.insert(kind, name, input);
+
+        Ok(())
+    }
+
+    /// Create the configured instances, followed by the restored ones, as if they were written
+    /// to the `new` leaves.
+    ///
+    /// Instances that fail to be created are skipped, and returned along with the reason.
+    pub fn instantiate(
+        self: &Arc<Self>,
+        config: &InstancesConfig,
+        restored: &[Record],
+    ) -> Vec<InstanceError> {
+        let mut errors = vec![];
+        let pending = pending(config, restored, &mut errors);
+
+        errors.extend(create_in_order(
+            pending,
+            |kind, name| self.exists(kind, name),
+            |kind, name, input| self.build_instance(kind, name, input),
+        ));
+
+        errors
+    }
+}
+
+/// Instance to create, along with the input it is built out of.
+type Pending<'a> = (Kind, &'a str, String);
+
+/// Instances of `config`, followed by the `restored` ones, which therefore lose to configured
+/// instances of the same name.
+fn pending<'a>(
+    config: &'a InstancesConfig,
+    restored: &'a [Record],
+    errors: &mut Vec<InstanceError>,
+) -> Vec<Pending<'a>> {
+    let mut pending = config
+        .connector
+        .iter()
+        .map(|c| (Kind::Connector, c.name.as_str(), c.build_input()))
+        .chain(
+            config
+                .os
+                .iter()
+                .map(|c| (Kind::Os, c.name.as_str(), c.build_input())),
+        )
+        .collect::<Vec<_>>();
+
+    for record in restored {
+        match Kind::from_branch(&record.branch) {
+            Some(kind) => pending.push((kind, record.name.as_str(), record.input.clone())),
+            None => errors.push(InstanceError::Build {
+                path: format!("{}/{}", record.branch, record.name),
+                error: BuildError::new(
+                    Error(ErrorOrigin::Backend, ErrorKind::InvalidPath),
+                    format!("unknown branch {}", record.branch),
+                ),
+            }),
+        }
+    }
+
+    pending
+}
+
+/// Create the `pending` instances through `build`, once the instance they are chained with
+/// `exists`.
+///
+/// Failures are returned, followed by the instances whose parent never got created.
+fn create_in_order(
+    mut pending: Vec<Pending>,
+    exists: impl Fn(Kind, &str) -> bool,
+    mut build: impl FnMut(Kind, &str, &str) -> BuildResult<()>,
+) -> Vec<InstanceError> {
+    let mut errors = vec![];
+
+    // Every round creates the instances whose parents exist by now
+    loop {
+        let len = pending.len();
+
+        pending.retain(|(kind, name, input)| {
+            let ready = split_args(input)
+                .0
+                .is_none_or(|cw| exists(kind.parent(), cw));
+
+            if ready {
+                if let Err(error) = build(*kind, name, input) {
+                    errors.push(InstanceError::Build {
+                        path: format!("{}/{}", kind.branch(), name),
+                        error,
+                    });
+                }
+            }
+
+            !ready
+        });
+
+        if pending.len() == len {
+            break;
+        }
+    }
+
+    errors.extend(
+        pending
+            .into_iter()
+            .map(|(kind, name, input)| InstanceError::MissingParent {
+                path: format!("{}/{}", kind.branch(), name),
+                parent: format!(
+                    "{}/{}",
+                    kind.parent().branch(),
+                    split_args(&input).0.unwrap_or_default()
+                ),
+            }),
+    );
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Instances created so far, refusing names that are taken.
+    #[derive(Default)]
+    struct Registry(RefCell<Vec<(Kind, String, String)>>);
+
+    impl Registry {
+        fn exists(&self, kind: Kind, name: &str) -> bool {
+            self.0
+                .borrow()
+                .iter()
+                .any(|(k, n, _)| *k == kind && n == name)
+        }
+
+        /// Same as `MemflowBackend::instantiate`.
+        fn instantiate(&self, config: &InstancesConfig, restored: &[Record]) -> Vec<InstanceError> {
+            let mut errors = vec![];
+            let pending = pending(config, restored, &mut errors);
+
+            errors.extend(create_in_order(
+                pending,
+                |kind, name| self.exists(kind, name),
+                |kind, name, input| {
+                    if self.exists(kind, name) {
+                        return Err(BuildError::new(
+                            Error(ErrorOrigin::Backend, ErrorKind::AlreadyExists),
+                            format!("{name} already exists"),
+                        ));
+                    }
+                    self.0.borrow_mut().push((kind, name.into(), input.into()));
+                    Ok(())
+                },
+            ));
+
+            errors
+        }
+
+        fn created(&self) -> Vec<(Kind, String, String)> {
+            self.0.borrow().clone()
+        }
+    }
+
+    fn instance(name: &str, plugin: &str, chain_with: Option<&str>) -> InstanceConfig {
+        InstanceConfig {
+            name: name.into(),
+            plugin: plugin.into(),
+            chain_with: chain_with.map(Into::into),
+            args: String::new(),
+        }
+    }
+
+    fn record(branch: &str, name: &str, input: &str) -> Record {
+        Record {
+            branch: branch.into(),
+            name: name.into(),
+            input: input.into(),
+        }
+    }
+
+    #[test]
+    fn child_before_parent() {
+        let config = InstancesConfig {
+            connector: vec![
+                instance("proc", "native", Some("win")),
+                instance("qemu", "qemu", None),
+            ],
+            os: vec![instance("win", "win32", Some("qemu"))],
+            state_file: None,
+        };
+
+        let registry = Registry::default();
+        let errors = registry.instantiate(&config, &[]);
+
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            registry.created(),
+            [
+                (Kind::Connector, "qemu".into(), "qemu:".into()),
+                (Kind::Os, "win".into(), "-c qemu win32:".into()),
+                (Kind::Connector, "proc".into(), "-c win native:".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_parent() {
+        let config = InstancesConfig {
+            os: vec![instance("win", "win32", Some("kvm"))],
+            ..Default::default()
+        };
+
+        let registry = Registry::default();
+        let errors = registry.instantiate(&config, &[]);
+
+        assert!(registry.created().is_empty());
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [InstanceError::MissingParent { path, parent }]
+                    if path == "os/win" && parent == "connector/kvm"
+            ),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn duplicate_name() {
+        let config = InstancesConfig {
+            connector: vec![
+                instance("qemu", "qemu", None),
+                instance("qemu", "kvm", None),
+            ],
+            ..Default::default()
+        };
+
+        let registry = Registry::default();
+        let errors = registry.instantiate(&config, &[]);
+
+        assert_eq!(
+            registry.created(),
+            [(Kind::Connector, "qemu".into(), "qemu:".into())]
+        );
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [InstanceError::Build { path, error }]
+                    if path == "connector/qemu" && error.error.1 == ErrorKind::AlreadyExists
+            ),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn configured_before_restored() {
+        let config = InstancesConfig {
+            connector: vec![instance("qemu", "qemu", None)],
+            ..Default::default()
+        };
+        let restored = [
+            record("connector", "qemu", "kvm:"),
+            record("os", "win", "-c qemu win32:"),
+            record("process", "init", "native:"),
+        ];
+
+        let registry = Registry::default();
+        let errors = registry.instantiate(&config, &restored);
+
+        assert_eq!(
+            registry.created(),
+            [
+                (Kind::Connector, "qemu".into(), "qemu:".into()),
+                (Kind::Os, "win".into(), "-c qemu win32:".into()),
+            ]
+        );
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [
+                    InstanceError::Build { path: unknown, .. },
+                    InstanceError::Build { path: taken, .. },
+                ] if unknown == "process/init" && taken == "connector/qemu"
+            ),
+            "{errors:?}"
+        );
+    }
+}
@@ -12,8 +12,11 @@ use std::sync::Arc;
 pub extern "C" fn on_node(node: &Node, ctx: CArc<c_void>) {
     node.plugins.register_mapping(
         "mem",
-        Mapping::Leaf(self_as_leaf::<ThreadedConnectorArc>, ctx),
+        Mapping::Leaf(self_as_leaf::<ConnectorRoot>, ctx.clone()),
     );
+
+    node.plugins
+        .register_mapping("spec", Mapping::Leaf(map_into_spec, ctx));
 }
 
 thread_types!(
@@ -22,7 +25,36 @@ thread_types!(
     ThreadedConnectorArc
 );
 
-impl Branch for ThreadedConnectorArc {
+#[repr(C)]
+#[derive(Clone, StableAbi)]
+pub struct ConnectorRoot {
+    connector: ThreadedConnectorArc,
+    spec: CArcSome<BuildSpec>,
+}
+
+impl core::ops::Deref for ConnectorRoot {
+    type Target = ThreadedConnectorArc;
+
+    fn deref(&self) -> &Self::Target {
+        &self.connector
+    }
+}
+
+impl ConnectorRoot {
+    pub fn new(connector: ThreadedConnectorArc, spec: BuildSpec) -> Self {
+        Self {
+            connector,
+            spec: spec.into(),
+        }
+    }
+
+    /// Input the connector was built out of.
+    pub fn spec(&self) -> &BuildSpec {
+        &self.spec
+    }
+}
+
+impl Branch for ConnectorRoot {
     fn get_entry(&self, path: &str, plugins: &CPluginStore) -> Result<DirEntry> {
         branch::get_entry(self, path, plugins)
     }
@@ -40,10 +72,10 @@ impl Branch for ThreadedConnectorArc {
     }
 }
 
-impl Leaf for ThreadedConnectorArc {
+impl Leaf for ConnectorRoot {
     fn open(&self) -> Result<FileOpsObj<c_void>> {
         Ok(FileOpsObj::new(
-            (**self).clone(),
+            (**self.connector).clone(),
             Some(ThreadedConnector::read),
             Some(ThreadedConnector::write),
             Some(ThreadedConnector::rpc),
@@ -62,8 +94,8 @@ impl Leaf for ThreadedConnectorArc {
     }
 }
 
-impl StrBuild<CArc<Arc<MemflowBackend>>> for ThreadedConnectorArc {
-    fn build(input: &str, ctx: &CArc<Arc<MemflowBackend>>) -> Result<ThreadedConnectorArc> {
+impl StrBuild<CArc<Arc<MemflowBackend>>> for ConnectorRoot {
+    fn build(input: &str, ctx: &CArc<Arc<MemflowBackend>>) -> BuildResult<Self> {
         let (chain_with, name, args) = split_args(input);
 
         let ctx = ctx.as_ref().ok_or(ErrorKind::NotFound)?;
@@ -72,7 +104,9 @@ impl StrBuild<CArc<Arc<MemflowBackend>>> for ThreadedConnectorArc {
             Some(
                 ctx.os
                     .get(cw)
-                    .ok_or(ErrorKind::NotFound)?
+                    .ok_or_else(|| {
+                        BuildError::new(ErrorKind::NotFound, format!("os/{cw} does not exist"))
+                    })?
                     .get_orig()
                     .clone(),
             )
@@ -81,16 +115,32 @@ impl StrBuild<CArc<Arc<MemflowBackend>>> for ThreadedConnectorArc {
         };
 
         ctx.inventory
+            .read()
+            .unwrap()
             .create_connector(
                 name,
                 chain_with,
-                Some(&str::parse(args).map_err(|_| ErrorKind::InvalidArgument)?),
+                Some(&str::parse(args).map_err(|e| {
+                    BuildError::new(
+                        ErrorKind::InvalidArgument,
+                        format!("invalid arguments: {e}"),
+                    )
+                })?),
             )
             .map(|c| ThreadedConnector::from(c).self_arc_up())
-            .map_err(|_| ErrorKind::Uninitialized.into())
+            .map(|c| Self::new(c, BuildSpec::new(input)))
+            .map_err(|e| build_error(e, format!("unable to create {name}")))
     }
 }
 
+extern "C" fn map_into_spec(
+    connector: &ConnectorRoot,
+    ctx: &CArc<c_void>,
+) -> COption<LeafArcBox<'static>> {
+    let file = FnFile::new(connector.spec.clone(), |spec| Ok(spec.to_string()));
+    COption::Some(trait_obj!((file, ctx.clone()) as Leaf))
+}
+
 impl ThreadedConnector {
     extern "C" fn read(&self, data: VecOps<RWData>) -> i32 {
         int_res_wrap! {
@@ -0,0 +1,260 @@
+//! Dependencies between chained instances, which keep instances from being removed while
+//! others are built on top of them.
+
+use crate::state::Record;
+use crate::util::split_args;
+use crate::MemflowBackend;
+
+use dashmap::DashMap;
+use filer::prelude::v1::*;
+use log::*;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+
+/// Branch of an instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Kind {
+    Connector,
+    Os,
+}
+
+impl Kind {
+    pub fn from_branch(branch: &str) -> Option<Self> {
+        match branch {
+            "connector" => Some(Kind::Connector),
+            "os" => Some(Kind::Os),
+            _ => None,
+        }
+    }
+
+    pub fn branch(self) -> &'static str {
+        match self {
+            Kind::Connector => "connector",
+            Kind::Os => "os",
+        }
+    }
+
+    /// Kind of the instances this kind is chained with.
+    pub fn parent(self) -> Kind {
+        match self {
+            Kind::Connector => Kind::Os,
+            Kind::Os => Kind::Connector,
+        }
+    }
+}
+
+/// Instances chained with another one, mapped to the instance they are chained with.
+#[derive(Default)]
+pub(crate) struct Chains {
+    chains: DashMap<(Kind, String), (Kind, String)>,
+    /// Held while instances get created or removed, so that no instance gets chained with one
+    /// that is being removed.
+    updates: Arc<Mutex<()>>,
+}
+
+/// Lock `updates`, which guards no data and thus can not be left inconsistent by a panic.
+fn lock_updates(updates: &Mutex<()>) -> MutexGuard<'_, ()> {
+    updates.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Chains {
+    /// Lock held while an instance gets created or removed.
+    pub fn lock(&self) -> MutexGuard<'_, ()> {
+        lock_updates(&self.updates)
+    }
+
+    /// Record the instance built out of `input`, if it is chained with another one.
+    pub fn insert(&self, kind: Kind, name: &str, input: &str) {
+        if let Some(parent) = split_args(input).0 {
+            self.chains
+                .insert((kind, name.into()), (kind.parent(), parent.into()));
+        }
+    }
+
+    pub fn remove(&self, kind: Kind, name: &str) {
+        self.chains.remove(&(kind, name.to_string()));
+    }
+
+    pub fn clear(&self) {
+        self.chains.clear();
+    }
+
+    /// Instances chained with `name`.
+    pub fn dependents(&self, kind: Kind, name: &str) -> Vec<(Kind, String)> {
+        self.chains
+            .iter()
+            .filter(|e| e.value().0 == kind && e.value().1 == name)
+            .map(|e| e.key().clone())
+            .collect()
+    }
+
+    /// Every instance of `kind` others are chained with, followed by its dependents, one
+    /// instance per line.
+    pub fn describe(&self, kind: Kind) -> String {
+        let mut dependents = BTreeMap::<String, Vec<String>>::new();
+
+        for e in self.chains.iter().filter(|e| e.value().0 == kind) {
+            let (dep_kind, dep_name) = e.key();
+            dependents
+                .entry(e.value().1.clone())
+                .or_default()
+                .push(format!("{}/{}", dep_kind.branch(), dep_name));
+        }
+
+        dependents
+            .into_iter()
+            .map(|(name, mut deps)| {
+                deps.sort();
+                format!("{name}: {}\n", deps.join(" "))
+            })
+            .collect()
+    }
+}
+
+impl MemflowBackend {
+    fn remove_entry(&self, kind: Kind, name: &str) -> bool {
+        match kind {
+            Kind::Connector => self.connector.remove(name),
+            Kind::Os => self.os.remove(name),
+        }
+    }
+
+    /// Remove the instances chained with `name`, or refuse to unless `recursive` is set.
+    fn remove_dependents(&self, kind: Kind, name: &str, recursive: bool) -> BuildResult<()> {
+        let dependents = self.chains.dependents(kind, name);
+
+        if dependents.is_empty() {
+            return Ok(());
+        }
+
+        if !recursive {
+            let dependents = dependents
+                .iter()
+                .map(|(kind, name)| format!("{}/{name}", kind.branch()))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            return Err(BuildError::new(
+                Error(ErrorOrigin::Backend, ErrorKind::InvalidArgument),
+                format!("{dependents} chained with {name}, remove with -r to remove them too"),
+            ));
+        }
+
+        for (kind, name) in dependents {
+            self.remove_dependents(kind, &name, true)?;
+            self.remove_entry(kind, &name);
+            self.forget(kind, &name);
+        }
+
+        Ok(())
+    }
+
+    /// Record the instance created through `new`.
+    fn created(&self, kind: Kind, name: &str, input: &str) {
+        self.chains.insert(kind, name, input);
+
+        if let Some(state) = &self.state {
+            let record = Record {
+                branch: kind.branch().into(),
+                name: name.into(),
+                input: input.into(),
+            };
+
+            if let Err(e) = state.insert(record) {
+                warn!(
+                    "unable to record {}/{name} in {}: {e}",
+                    kind.branch(),
+                    state.path().display()
+                );
+            }
+        }
+    }
+
+    /// Forget about a removed instance, including its record in the state file.
+    fn forget(&self, kind: Kind, name: &str) {
+        self.chains.remove(kind, name);
+
+        if let Some(state) = &self.state {
+            if let Err(e) = state.remove(kind.branch(), name) {
+                warn!(
+                    "unable to remove {}/{name} from {}: {e}",
+                    kind.branch(),
+                    state.path().display()
+                );
+            }
+        }
+    }
+}
+
+/// Keeps track of the instances created and removed in a single branch.
+pub(crate) struct InstanceObserver {
+    memflow: Weak<MemflowBackend>,
+    kind: Kind,
+    updates: Arc<Mutex<()>>,
+}
+
+impl InstanceObserver {
+    pub fn new(memflow: &Arc<MemflowBackend>, kind: Kind) -> Self {
+        Self {
+            memflow: Arc::downgrade(memflow),
+            kind,
+            updates: memflow.chains.updates.clone(),
+        }
+    }
+}
+
+impl EntryObserver for InstanceObserver {
+    fn created(&self, name: &str, input: &str) {
+        if let Some(memflow) = self.memflow.upgrade() {
+            // Inputs written with echo end with a newline
+            memflow.created(self.kind, name.trim(), input.trim());
+        }
+    }
+
+    fn removing(&self, name: &str, recursive: bool) -> BuildResult<()> {
+        match self.memflow.upgrade() {
+            Some(memflow) => memflow.remove_dependents(self.kind, name, recursive),
+            None => Ok(()),
+        }
+    }
+
+    fn removed(&self, name: &str) {
+        if let Some(memflow) = self.memflow.upgrade() {
+            memflow.forget(self.kind, name.trim());
+        }
+    }
+
+    // Shared by both branches, as their instances are chained with each other
+    fn lock(&self) -> Option<MutexGuard<'_, ()>> {
+        Some(lock_updates(&self.updates))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn chain_dependents() {
+        let chains = Chains::default();
+        chains.insert(Kind::Connector, "vm", "qemu:my-vm");
+        chains.insert(Kind::Os, "win", "-c vm win32::arch=x64");
+        chains.insert(Kind::Os, "lin", "-c vm linux");
+        chains.insert(Kind::Connector, "kernel", "-c win kvm");
+
+        let mut dependents = chains.dependents(Kind::Connector, "vm");
+        dependents.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            dependents,
+            vec![(Kind::Os, "lin".into()), (Kind::Os, "win".into())]
+        );
+        assert!(chains.dependents(Kind::Os, "vm").is_empty());
+
+        assert_eq!(chains.describe(Kind::Connector), "vm: os/lin os/win\n");
+        assert_eq!(chains.describe(Kind::Os), "win: connector/kernel\n");
+
+        chains.remove(Kind::Os, "lin");
+        assert_eq!(chains.describe(Kind::Connector), "vm: os/win\n");
+    }
+}
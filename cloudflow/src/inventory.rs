@@ -0,0 +1,231 @@
+//! Branch listing the connector and OS plugins found by the inventory, so that the inputs of
+//! `new` can be looked up from within the tree.
+
+use crate::deps::Kind;
+
+use cglue::trait_group::c_void;
+use filer::branch;
+use filer::prelude::v1::{Error, ErrorKind, ErrorOrigin, Result, *};
+use memflow::prelude::v1::*;
+
+use std::sync::{Arc, RwLock};
+
+pub(crate) type SharedInventory = Arc<RwLock<Inventory>>;
+
+/// Root of the `inventory` backend, holding the `connector` and `os` plugin lists.
+#[derive(Clone)]
+pub(crate) struct InventoryRoot {
+    inventory: SharedInventory,
+}
+
+impl InventoryRoot {
+    pub fn new(inventory: SharedInventory) -> Self {
+        Self { inventory }
+    }
+
+    fn plugins(&self, kind: Kind) -> PluginList {
+        PluginList {
+            inventory: self.inventory.clone(),
+            kind,
+        }
+    }
+}
+
+impl Branch for InventoryRoot {
+    fn get_entry(&self, path: &str, plugins: &CPluginStore) -> Result<DirEntry> {
+        let (entry, path) = branch::split_path(path);
+
+        match (entry, path) {
+            ("connector", path) => {
+                branch::forward_entry(self.plugins(Kind::Connector), no_ctx(), path, plugins)
+            }
+            ("os", path) => branch::forward_entry(self.plugins(Kind::Os), no_ctx(), path, plugins),
+            ("rescan", None) => Ok(DirEntry::Leaf(trait_obj!((
+                Rescan(self.inventory.clone()),
+                no_ctx()
+            ) as Leaf))),
+            _ => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn list(&self, _: &CPluginStore, out: &mut OpaqueCallback<BranchListEntry>) -> Result<()> {
+        for kind in [Kind::Connector, Kind::Os] {
+            let _ = out.call(BranchListEntry::new(
+                kind.branch().into(),
+                DirEntry::Branch(trait_obj!((self.plugins(kind), no_ctx()) as Branch)),
+            ));
+        }
+
+        let _ = out.call(BranchListEntry::new(
+            "rescan".into(),
+            DirEntry::Leaf(trait_obj!(
+                (Rescan(self.inventory.clone()), no_ctx()) as Leaf
+            )),
+        ));
+
+        Ok(())
+    }
+}
+
+fn no_ctx() -> CArc<c_void> {
+    CArc::default()
+}
+
+/// Plugins of a single kind, by name.
+#[derive(Clone)]
+struct PluginList {
+    inventory: SharedInventory,
+    kind: Kind,
+}
+
+impl PluginList {
+    fn names(&self) -> Vec<String> {
+        let inventory = self.inventory.read().unwrap();
+
+        match self.kind {
+            Kind::Connector => inventory.available_connectors(),
+            Kind::Os => inventory.available_os(),
+        }
+    }
+}
+
+impl Branch for PluginList {
+    fn get_entry(&self, path: &str, plugins: &CPluginStore) -> Result<DirEntry> {
+        let (name, path) = branch::split_path(path);
+
+        if !self.names().iter().any(|n| n == name) {
+            return Err(Error(ErrorOrigin::Branch, ErrorKind::NotFound));
+        }
+
+        let plugin = Plugin {
+            list: self.clone(),
+            name: name.into(),
+        };
+
+        branch::forward_entry(plugin, no_ctx(), path, plugins)
+    }
+
+    fn list(&self, _: &CPluginStore, out: &mut OpaqueCallback<BranchListEntry>) -> Result<()> {
+        for name in self.names() {
+            let plugin = Plugin {
+                list: self.clone(),
+                name: name.clone(),
+            };
+
+            let entry = DirEntry::Branch(trait_obj!((plugin, no_ctx()) as Branch));
+            if !out.call(BranchListEntry::new(name.into(), entry)) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Leaves describing a single plugin.
+#[derive(Clone)]
+struct Plugin {
+    list: PluginList,
+    name: String,
+}
+
+impl Plugin {
+    /// Names of the leaves of the plugin, which only connectors have targets for.
+    ///
+    /// There are no `version` and `description` leaves, as `Inventory` does not expose the
+    /// `PluginDescriptor` of its plugins.
+    fn leaves(&self) -> &'static [&'static str] {
+        match self.list.kind {
+            Kind::Connector => &["help", "targets"],
+            Kind::Os => &["help"],
+        }
+    }
+
+    fn leaf(&self, name: &str) -> Option<LeafArcBox<'static>> {
+        let file = match name {
+            "help" => FnFile::new(self.clone(), Self::help),
+            "targets" if self.list.kind == Kind::Connector => {
+                FnFile::new(self.clone(), Self::targets)
+            }
+            _ => return None,
+        };
+
+        Some(trait_obj!((file, no_ctx()) as Leaf))
+    }
+
+    /// Arguments accepted by the plugin, as described by the plugin itself.
+    fn help(&self) -> Result<String> {
+        let inventory = self.list.inventory.read().unwrap();
+
+        let help = match self.list.kind {
+            Kind::Connector => inventory.connector_help(&self.name),
+            Kind::Os => inventory.os_help(&self.name),
+        };
+
+        help.map(|help| help + "\n")
+            .map_err(|_| Error(ErrorOrigin::Leaf, ErrorKind::NotSupported))
+    }
+
+    /// Targets the connector can connect to, one per line.
+    fn targets(&self) -> Result<String> {
+        self.list
+            .inventory
+            .read()
+            .unwrap()
+            .connector_target_list(&self.name)
+            .map(|targets| targets.iter().map(|t| format!("{}\n", t.name)).collect())
+            .map_err(|_| Error(ErrorOrigin::Leaf, ErrorKind::NotSupported))
+    }
+}
+
+impl Branch for Plugin {
+    fn get_entry(&self, path: &str, _: &CPluginStore) -> Result<DirEntry> {
+        self.leaf(path)
+            .map(DirEntry::Leaf)
+            .ok_or(Error(ErrorOrigin::Branch, ErrorKind::NotFound))
+    }
+
+    fn list(&self, _: &CPluginStore, out: &mut OpaqueCallback<BranchListEntry>) -> Result<()> {
+        for name in self.leaves() {
+            if let Some(leaf) = self.leaf(name) {
+                let _ = out.call(BranchListEntry::new((*name).into(), DirEntry::Leaf(leaf)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Write-only leaf scanning for plugins again, when written to.
+#[derive(Clone)]
+struct Rescan(SharedInventory);
+
+impl Leaf for Rescan {
+    fn open(&self) -> Result<FileOpsObj<c_void>> {
+        Ok(FileOpsObj::new(
+            self.clone().into(),
+            None,
+            Some(Self::write),
+            None,
+        ))
+    }
+
+    fn metadata(&self) -> Result<NodeMetadata> {
+        Ok(NodeMetadata {
+            is_branch: false,
+            has_write: true,
+            ..Default::default()
+        })
+    }
+}
+
+impl Rescan {
+    extern "C" fn write(&self, data: VecOps<ROData>) -> i32 {
+        // Whatever was written, it only asks for a rescan
+        data.inp.for_each(drop);
+
+        *self.0.write().unwrap() = Inventory::scan();
+
+        0
+    }
+}
@@ -3,16 +3,24 @@ extern crate filer;
 
 pub use cglue::slice::CSliceMut;
 use cglue::trait_group::c_void;
-use connector::ThreadedConnectorArc;
+use config::{InstanceError, InstancesConfig};
+use connector::ConnectorRoot;
+use deps::{Chains, InstanceObserver, Kind};
 use filer::prelude::v1::*;
+use inventory::{InventoryRoot, SharedInventory};
 use memflow::prelude::v1::*;
 use os::OsRoot;
+use state::StateFile;
 use std::sync::Arc;
 
+pub mod config;
 pub mod connector;
+mod deps;
+mod inventory;
 pub mod module;
 pub mod os;
 pub mod process;
+pub mod state;
 pub mod util;
 
 const BUILTIN_PLUGINS: &[extern "C" fn(&Node, CArc<c_void>)] = &[
@@ -23,9 +31,42 @@ const BUILTIN_PLUGINS: &[extern "C" fn(&Node, CArc<c_void>)] = &[
 ];
 
 pub fn create_node() -> CArcSome<Node> {
+    create_node_with(&Default::default()).0
+}
+
+/// Create a node, along with the configured connector and OS instances, and the ones restored
+/// from the state file.
+///
+/// Instances that fail to be created do not prevent the node from being created, instead they
+/// are returned along with the reason. The backend holding the instances is returned as well, to
+/// drop them once the node is shut down.
+pub fn create_node_with(
+    instances: &InstancesConfig,
+) -> (CArcSome<Node>, Arc<MemflowBackend>, Vec<InstanceError>) {
     let backend = NodeBackend::default();
 
-    MemflowBackend::to_node(&backend);
+    let mut errors = vec![];
+
+    let state = instances
+        .state_file
+        .as_ref()
+        .and_then(|path| match StateFile::open(path) {
+            Ok(state) => Some(Arc::new(state)),
+            Err(error) => {
+                // Recording without having read the file would overwrite it
+                errors.push(InstanceError::State {
+                    path: path.clone(),
+                    error,
+                });
+                None
+            }
+        });
+
+    let restored = state.as_ref().map(|s| s.records()).unwrap_or_default();
+
+    let memflow = MemflowBackend::new_arc(state);
+    memflow.add_to_node(&backend);
+    errors.extend(memflow.instantiate(instances, &restored));
 
     let node = Node::new(backend);
 
@@ -33,13 +74,15 @@ pub fn create_node() -> CArcSome<Node> {
         plugin(&node, Default::default());
     }
 
-    node.into()
+    (node.into(), memflow, errors)
 }
 
 pub struct MemflowBackend {
-    connector: Arc<LocalBackend<ThreadedConnectorArc, Arc<Self>>>,
+    connector: Arc<LocalBackend<ConnectorRoot, Arc<Self>>>,
     os: Arc<LocalBackend<OsRoot, Arc<Self>>>,
-    inventory: Inventory,
+    inventory: SharedInventory,
+    chains: Chains,
+    state: Option<Arc<StateFile>>,
 }
 
 impl Default for MemflowBackend {
@@ -47,14 +90,19 @@ impl Default for MemflowBackend {
         Self {
             connector: LocalBackend::default().with_new().into(),
             os: LocalBackend::default().with_new().into(),
-            inventory: Inventory::scan(),
+            inventory: Arc::new(Inventory::scan().into()),
+            chains: Default::default(),
+            state: None,
         }
     }
 }
 
 impl MemflowBackend {
-    fn new_arc() -> CArcSome<Self> {
-        let ret = Arc::from(Self::default());
+    fn new_arc(state: Option<Arc<StateFile>>) -> Arc<Self> {
+        let ret = Arc::from(Self {
+            state,
+            ..Self::default()
+        });
 
         // SAFETY: we are not reading the underlying object from anywhere else.
         unsafe {
@@ -64,17 +112,154 @@ impl MemflowBackend {
 
             (*ptr_mut(&*ret.connector)).set_context(ret.clone());
             (*ptr_mut(&*ret.os)).set_context(ret.clone());
+
+            (*ptr_mut(&*ret.connector)).set_observer(InstanceObserver::new(&ret, Kind::Connector));
+            (*ptr_mut(&*ret.os)).set_observer(InstanceObserver::new(&ret, Kind::Os));
+
+            let memflow = Arc::downgrade(&ret);
+            (*ptr_mut(&*ret.connector)).add_text_leaf("deps", move || {
+                memflow
+                    .upgrade()
+                    .map(|m| m.chains.describe(Kind::Connector))
+                    .unwrap_or_default()
+            });
+            let memflow = Arc::downgrade(&ret);
+            (*ptr_mut(&*ret.os)).add_text_leaf("deps", move || {
+                memflow
+                    .upgrade()
+                    .map(|m| m.chains.describe(Kind::Os))
+                    .unwrap_or_default()
+            });
         }
 
-        ret.into()
+        ret
     }
 
     fn add_to_node(&self, backend: &NodeBackend) {
         backend.add_backend("connector", self.connector.clone());
         backend.add_backend("os", self.os.clone());
+        backend.add_backend(
+            "inventory",
+            BranchBackend::new(InventoryRoot::new(self.inventory.clone())),
+        );
+    }
+
+    /// Drop all instances, OSes first as they are built on top of connectors.
+    ///
+    /// Connectors chained with an OS keep it alive until they are dropped as well. Unlike
+    /// removing instances through `rm`, this keeps them in the state file.
+    pub fn drop_instances(&self) {
+        drop_in_order(&self.os, &self.connector);
+        self.chains.clear();
     }
 
     pub fn to_node(backend: &NodeBackend) {
-        Self::new_arc().add_to_node(backend)
+        Self::new_arc(None).add_to_node(backend)
+    }
+}
+
+/// Drop the instances of `os` before the ones of `connector`.
+fn drop_in_order<O, C, X>(os: &LocalBackend<O, X>, connector: &LocalBackend<C, X>) {
+    os.clear();
+    connector.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::ThreadedConnector;
+    use crate::os::{OsBase, ThreadedOs};
+    use crate::state::Record;
+    use crate::util::BuildSpec;
+    use memflow::dummy::{DummyMemory, DummyOs};
+    use std::sync::Mutex;
+
+    fn record(branch: &str, name: &str, input: &str) -> Record {
+        Record {
+            branch: branch.into(),
+            name: name.into(),
+            input: input.into(),
+        }
+    }
+
+    /// Instance recording its name once dropped.
+    struct Instance(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Drop for Instance {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    #[test]
+    pub fn drop_instances_in_order() {
+        let dropped = Arc::new(Mutex::new(vec![]));
+
+        let connector = LocalBackend::<Instance>::default();
+        connector.insert("qemu", Instance("qemu", dropped.clone()));
+
+        let os = LocalBackend::<Instance>::default();
+        os.insert("win", Instance("win", dropped.clone()));
+        os.insert("linux", Instance("linux", dropped.clone()));
+
+        drop_in_order(&os, &connector);
+
+        let mut dropped = dropped.lock().unwrap().clone();
+        assert_eq!(dropped.pop(), Some("qemu"));
+        dropped.sort();
+        assert_eq!(dropped, ["linux", "win"]);
+
+        assert!(os.get("win").is_none());
+        assert!(connector.get("qemu").is_none());
+    }
+
+    #[test]
+    pub fn drop_instances_keeps_state() {
+        let path = std::env::temp_dir().join(format!("cloudflow-drop-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let state = Arc::new(StateFile::open(&path).unwrap());
+        let memflow = MemflowBackend::new_arc(Some(state.clone()));
+
+        let connector = memflow::plugins::connector::create_instance(
+            DummyMemory::new(memflow::types::size::mb(1)),
+            CArc::default(),
+            &Default::default(),
+            true,
+        );
+        let connector = ThreadedConnector::from(connector).self_arc_up();
+        memflow.connector.insert(
+            "mem",
+            ConnectorRoot::new(connector, BuildSpec::new("dummy")),
+        );
+        state.insert(record("connector", "mem", "dummy")).unwrap();
+
+        let os = memflow::plugins::os::create_instance(
+            DummyOs::new(DummyMemory::new(memflow::types::size::mb(1))),
+            CArc::default(),
+            &Default::default(),
+        );
+        let os = OsBase::new(ThreadedOs::from(os).self_arc_up());
+        memflow
+            .os
+            .insert("dummy", OsRoot::new(os, BuildSpec::new("-c mem dummy")));
+        memflow.chains.insert(Kind::Os, "dummy", "-c mem dummy");
+        state.insert(record("os", "dummy", "-c mem dummy")).unwrap();
+
+        memflow.drop_instances();
+
+        assert!(memflow.connector.get("mem").is_none());
+        assert!(memflow.os.get("dummy").is_none());
+        assert_eq!(memflow.chains.describe(Kind::Connector), "");
+
+        let records = StateFile::open(&path).unwrap().records();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                record("connector", "mem", "dummy"),
+                record("os", "dummy", "-c mem dummy")
+            ]
+        );
     }
 }
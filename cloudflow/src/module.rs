@@ -1,3 +1,4 @@
+use crate::os::ThreadedOsArc;
 use crate::process::ThreadedProcessArc;
 use crate::util::*;
 use abi_stable::StableAbi;
@@ -7,12 +8,29 @@ use filer::branch;
 use filer::prelude::v1::{Error, ErrorKind, ErrorOrigin, Result, *};
 use memflow::prelude::v1::*;
 
+use dashmap::DashMap;
+
+use std::collections::HashSet;
+
 pub extern "C" fn on_node(node: &Node, ctx: CArc<c_void>) {
     node.plugins
         .register_mapping("mem", Mapping::Leaf(self_as_leaf::<ModuleArc>, ctx.clone()));
 
     node.plugins
         .register_mapping("info", Mapping::Leaf(map_into_info, ctx.clone()));
+
+    node.plugins.register_mapping(
+        "exports",
+        Mapping::Branch(SymbolList::map_into_exports, ctx.clone()),
+    );
+
+    node.plugins.register_mapping(
+        "imports",
+        Mapping::Branch(SymbolList::map_into_imports, ctx.clone()),
+    );
+
+    node.plugins
+        .register_mapping("sections", Mapping::Branch(SectionList::map_into, ctx));
 }
 
 arc_types!(ModuleBase, Module, ModuleArc);
@@ -57,17 +75,80 @@ impl Leaf for ModuleArc {
     }
 }
 
+/// Memory a module is mapped in.
+#[repr(u8)]
+#[derive(StableAbi, Clone)]
+pub enum ModuleMem {
+    Process(ThreadedProcessArc),
+    /// Kernel modules are mapped in the memory of the OS itself.
+    Kernel(ThreadedOsArc),
+}
+
+impl ModuleMem {
+    fn read_raw_iter(&self, data: ReadRawMemOps) -> Result<()> {
+        match self {
+            ModuleMem::Process(process) => process.get().read_raw_iter(data),
+            ModuleMem::Kernel(os) => as_mut!(os.get() impl MemoryView)
+                .ok_or(Error(ErrorOrigin::Read, ErrorKind::NotImplemented))?
+                .read_raw_iter(data),
+        }
+        .map_err(|_| Error(ErrorOrigin::Read, ErrorKind::Unknown))
+    }
+
+    fn write_raw_iter(&self, data: WriteRawMemOps) -> Result<()> {
+        match self {
+            ModuleMem::Process(process) => process.get().write_raw_iter(data),
+            ModuleMem::Kernel(os) => as_mut!(os.get() impl MemoryView)
+                .ok_or(Error(ErrorOrigin::Write, ErrorKind::NotImplemented))?
+                .write_raw_iter(data),
+        }
+        .map_err(|_| Error(ErrorOrigin::Write, ErrorKind::Unknown))
+    }
+
+    fn exports(&self, info: &ModuleInfo) -> Result<Vec<ExportInfo>> {
+        match self {
+            ModuleMem::Process(process) => process.get().module_export_list(info),
+            ModuleMem::Kernel(os) => os.get().module_export_list(info),
+        }
+        .map_err(|_| Error(ErrorOrigin::Branch, ErrorKind::Unknown))
+    }
+
+    fn imports(&self, info: &ModuleInfo) -> Result<Vec<ImportInfo>> {
+        match self {
+            ModuleMem::Process(process) => process.get().module_import_list(info),
+            ModuleMem::Kernel(os) => os.get().module_import_list(info),
+        }
+        .map_err(|_| Error(ErrorOrigin::Branch, ErrorKind::Unknown))
+    }
+
+    fn sections(&self, info: &ModuleInfo) -> Result<Vec<SectionInfo>> {
+        match self {
+            ModuleMem::Process(process) => process.get().module_section_list(info),
+            ModuleMem::Kernel(os) => os.get().module_section_list(info),
+        }
+        .map_err(|_| Error(ErrorOrigin::Branch, ErrorKind::Unknown))
+    }
+}
+
 #[repr(C)]
 #[derive(StableAbi, Clone)]
 pub struct ModuleBase {
-    process: ThreadedProcessArc,
+    mem: ModuleMem,
     module_info: ModuleInfo,
 }
 use std::cell::RefCell;
 impl ModuleBase {
     pub fn new(process: ThreadedProcessArc, module_info: ModuleInfo) -> Self {
         Self {
-            process,
+            mem: ModuleMem::Process(process),
+            module_info,
+        }
+    }
+
+    /// Module loaded in the kernel of `os`.
+    pub fn new_kernel(os: ThreadedOsArc, module_info: ModuleInfo) -> Self {
+        Self {
+            mem: ModuleMem::Kernel(os),
             module_info,
         }
     }
@@ -126,11 +207,7 @@ impl ModuleBase {
                 let out_fail = Some(out_fail);
 
                 // create a new MemOps object with the wrapped values
-                MemOps::with_raw(inp, out, out_fail, |data| {
-                    self.process.get()
-                        .read_raw_iter(data)
-                        .map_err(|_| Error(ErrorOrigin::Read, ErrorKind::Unknown))
-                    })
+                MemOps::with_raw(inp, out, out_fail, |data| self.mem.read_raw_iter(data))
             })
         }
     }
@@ -189,11 +266,7 @@ impl ModuleBase {
                 let out_fail = Some(out_fail);
 
                 // create a new MemOps object with the wrapped values
-                MemOps::with_raw(inp, out, out_fail, |data| {
-                    self.process.get()
-                        .write_raw_iter(data)
-                        .map_err(|_| Error(ErrorOrigin::Read, ErrorKind::Unknown))
-                    })
+                MemOps::with_raw(inp, out, out_fail, |data| self.mem.write_raw_iter(data))
             })
         }
     }
@@ -212,3 +285,290 @@ extern "C" fn map_into_info(
     });
     COption::Some(trait_obj!((file, ctx.clone()) as Leaf))
 }
+
+#[derive(Clone, Copy)]
+enum SymbolKind {
+    Export,
+    Import,
+}
+
+/// Symbols exported or imported by a module, each a leaf holding its address.
+#[derive(Clone)]
+struct SymbolList {
+    module: ModuleArc,
+    kind: SymbolKind,
+    ctx: CArc<c_void>,
+    name_cache: CArcSome<DashMap<String, Address>>,
+}
+
+impl SymbolList {
+    extern "C" fn map_into_exports(
+        module: &ModuleArc,
+        ctx: &CArc<c_void>,
+    ) -> COption<BranchArcBox<'static>> {
+        Self::map_into(module, SymbolKind::Export, ctx)
+    }
+
+    extern "C" fn map_into_imports(
+        module: &ModuleArc,
+        ctx: &CArc<c_void>,
+    ) -> COption<BranchArcBox<'static>> {
+        Self::map_into(module, SymbolKind::Import, ctx)
+    }
+
+    fn map_into(
+        module: &ModuleArc,
+        kind: SymbolKind,
+        ctx: &CArc<c_void>,
+    ) -> COption<BranchArcBox<'static>> {
+        let list = Self {
+            module: module.clone(),
+            kind,
+            ctx: ctx.clone(),
+            name_cache: DashMap::default().into(),
+        };
+        COption::Some(trait_obj!((list, ctx.clone()) as Branch))
+    }
+
+    /// Names of the symbols along with their addresses, which are cached for lookups by name.
+    fn symbols(&self) -> Result<Vec<(String, Address)>> {
+        let info = &self.module.module_info;
+
+        let symbols: Vec<_> = match self.kind {
+            SymbolKind::Export => self
+                .module
+                .mem
+                .exports(info)?
+                .into_iter()
+                .map(|e| (e.name.to_string(), info.base + e.offset))
+                .collect(),
+            SymbolKind::Import => self
+                .module
+                .mem
+                .imports(info)?
+                .into_iter()
+                .map(|i| (i.name.to_string(), info.base + i.offset))
+                .collect(),
+        };
+
+        let symbols = first_by_name(symbols);
+
+        for (name, address) in &symbols {
+            self.name_cache.insert(name.clone(), *address);
+        }
+
+        Ok(symbols)
+    }
+
+    fn leaf(&self, address: Address) -> LeafArcBox<'static> {
+        let file = FnFile::new(address, |address| Ok(format!("{:x}\n", address)));
+        trait_obj!((file, self.ctx.clone()) as Leaf)
+    }
+}
+
+/// Symbols without the ones named the same as an earlier symbol, which can not be looked up.
+fn first_by_name(symbols: Vec<(String, Address)>) -> Vec<(String, Address)> {
+    let mut names = HashSet::new();
+
+    symbols
+        .into_iter()
+        .filter(|(name, _)| names.insert(name.clone()))
+        .collect()
+}
+
+impl Branch for SymbolList {
+    fn get_entry(&self, path: &str, _plugins: &CPluginStore) -> Result<DirEntry> {
+        let address = match self.name_cache.get(path) {
+            Some(address) => *address,
+            None => self
+                .symbols()?
+                .into_iter()
+                .find(|(name, _)| name == path)
+                .map(|(_, address)| address)
+                .ok_or(Error(ErrorOrigin::Branch, ErrorKind::NotFound))?,
+        };
+
+        Ok(DirEntry::Leaf(self.leaf(address)))
+    }
+
+    fn list(
+        &self,
+        _plugins: &CPluginStore,
+        out: &mut OpaqueCallback<BranchListEntry>,
+    ) -> Result<()> {
+        for (name, address) in self.symbols()? {
+            if !out.call(BranchListEntry::new(
+                name.into(),
+                DirEntry::Leaf(self.leaf(address)),
+            )) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sections of a module, by name.
+#[derive(Clone)]
+struct SectionList {
+    module: ModuleArc,
+    ctx: CArc<c_void>,
+}
+
+impl SectionList {
+    extern "C" fn map_into(
+        module: &ModuleArc,
+        ctx: &CArc<c_void>,
+    ) -> COption<BranchArcBox<'static>> {
+        let list = Self {
+            module: module.clone(),
+            ctx: ctx.clone(),
+        };
+        COption::Some(trait_obj!((list, ctx.clone()) as Branch))
+    }
+
+    fn section(&self, info: SectionInfo) -> Section {
+        Section {
+            module: self.module.clone(),
+            info,
+            ctx: self.ctx.clone(),
+        }
+    }
+}
+
+impl Branch for SectionList {
+    fn get_entry(&self, path: &str, plugins: &CPluginStore) -> Result<DirEntry> {
+        let (name, path) = branch::split_path(path);
+
+        let info = self
+            .module
+            .mem
+            .sections(&self.module.module_info)?
+            .into_iter()
+            .find(|s| &*s.name == name)
+            .ok_or(Error(ErrorOrigin::Branch, ErrorKind::NotFound))?;
+
+        branch::forward_entry(self.section(info), self.ctx.clone(), path, plugins)
+    }
+
+    fn list(
+        &self,
+        _plugins: &CPluginStore,
+        out: &mut OpaqueCallback<BranchListEntry>,
+    ) -> Result<()> {
+        let mut names = HashSet::new();
+
+        for info in self.module.mem.sections(&self.module.module_info)? {
+            let name = info.name.to_string();
+
+            if names.insert(name.clone()) {
+                let entry =
+                    DirEntry::Branch(trait_obj!((self.section(info), self.ctx.clone()) as Branch));
+                if !out.call(BranchListEntry::new(name.into(), entry)) {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A single section, readable through its `mem` leaf.
+#[derive(Clone)]
+struct Section {
+    module: ModuleArc,
+    info: SectionInfo,
+    ctx: CArc<c_void>,
+}
+
+impl Section {
+    /// Memory of the section, read and written the same way as the memory of a module.
+    fn mem(&self) -> LeafArcBox<'static> {
+        let section = ModuleArc::from(ModuleBase {
+            mem: self.module.mem.clone(),
+            module_info: section_info(&self.module.module_info, &self.info),
+        });
+
+        trait_obj!((section, self.ctx.clone()) as Leaf)
+    }
+}
+
+/// Module spanning only the memory of `section`.
+fn section_info(module: &ModuleInfo, section: &SectionInfo) -> ModuleInfo {
+    ModuleInfo {
+        base: section.base,
+        size: section.size,
+        ..module.clone()
+    }
+}
+
+impl Branch for Section {
+    fn get_entry(&self, path: &str, _plugins: &CPluginStore) -> Result<DirEntry> {
+        match path {
+            "mem" => Ok(DirEntry::Leaf(self.mem())),
+            _ => Err(Error(ErrorOrigin::Branch, ErrorKind::NotFound)),
+        }
+    }
+
+    fn list(
+        &self,
+        _plugins: &CPluginStore,
+        out: &mut OpaqueCallback<BranchListEntry>,
+    ) -> Result<()> {
+        let _ = out.call(BranchListEntry::new(
+            "mem".into(),
+            DirEntry::Leaf(self.mem()),
+        ));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn symbols_first_by_name() {
+        let symbols = vec![
+            ("open".to_string(), Address::from(0x1000u64)),
+            ("close".to_string(), Address::from(0x2000u64)),
+            ("open".to_string(), Address::from(0x3000u64)),
+        ];
+
+        assert_eq!(
+            first_by_name(symbols),
+            vec![
+                ("open".to_string(), Address::from(0x1000u64)),
+                ("close".to_string(), Address::from(0x2000u64)),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn section_bounds() {
+        let module = ModuleInfo {
+            address: Address::from(0x10u64),
+            parent_process: Address::NULL,
+            base: Address::from(0x40_0000u64),
+            size: 0x10_0000,
+            name: "kernel32.dll".into(),
+            path: "C:\\Windows\\System32\\kernel32.dll".into(),
+            arch: ArchitectureIdent::X86(64, false),
+        };
+        let section = SectionInfo {
+            name: ".text".into(),
+            base: Address::from(0x40_1000u64),
+            size: 0x2000,
+        };
+
+        let info = section_info(&module, &section);
+
+        // Offsets within the leaf start at the section, and end along with it
+        assert_eq!(info.base, section.base);
+        assert_eq!(info.size, section.size);
+        assert_eq!(&*info.name, &*module.name);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::module::{ModuleArc, ModuleBase};
 use crate::process::{LazyProcessArc, LazyProcessBase};
 use crate::util::*;
 use crate::MemflowBackend;
@@ -10,14 +11,28 @@ use filer::prelude::v1::{Error, ErrorKind, ErrorOrigin, Result, *};
 use memflow::prelude::v1::*;
 use num::Num;
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 pub extern "C" fn on_node(node: &Node, ctx: CArc<c_void>) {
     node.plugins
         .register_mapping("os", Mapping::Leaf(self_as_leaf::<OsRoot>, ctx.clone()));
 
+    node.plugins.register_mapping(
+        "processes",
+        Mapping::Branch(ProcessList::map_into, ctx.clone()),
+    );
+
+    node.plugins.register_mapping(
+        "modules",
+        Mapping::Branch(KernelModuleList::map_into, ctx.clone()),
+    );
+
     node.plugins
-        .register_mapping("processes", Mapping::Branch(ProcessList::map_into, ctx));
+        .register_mapping("info", Mapping::Leaf(map_into_info, ctx.clone()));
+
+    node.plugins
+        .register_mapping("spec", Mapping::Leaf(map_into_spec, ctx));
 }
 
 thread_types!(OsInstanceArcBox<'static>, ThreadedOs, ThreadedOsArc);
@@ -29,6 +44,16 @@ pub struct OsBase {
     pub(crate) ctx: CArc<c_void>,
 }
 
+impl OsBase {
+    pub fn new(os: ThreadedOsArc) -> Self {
+        Self {
+            os,
+            // TODO: set ctx
+            ctx: Default::default(),
+        }
+    }
+}
+
 impl core::ops::Deref for OsBase {
     type Target = ThreadedOsArc;
 
@@ -42,6 +67,8 @@ impl core::ops::Deref for OsBase {
 pub struct OsRoot {
     os: OsBase,
     plist: CArcSome<c_void>,
+    mlist: CArcSome<c_void>,
+    spec: CArcSome<BuildSpec>,
 }
 
 impl core::ops::Deref for OsRoot {
@@ -52,21 +79,32 @@ impl core::ops::Deref for OsRoot {
     }
 }
 
-impl From<OsBase> for OsRoot {
-    fn from(os: OsBase) -> Self {
+impl OsRoot {
+    pub fn new(os: OsBase, spec: BuildSpec) -> Self {
         Self {
             plist: CArcSome::from(ProcessList::from(os.clone())).into_opaque(),
+            mlist: CArcSome::from(KernelModuleList::from(os.clone())).into_opaque(),
             os,
+            spec: spec.into(),
         }
     }
-}
 
-impl OsRoot {
+    /// Input the OS was built out of.
+    pub fn spec(&self) -> &BuildSpec {
+        &self.spec
+    }
+
     unsafe fn plist(&self) -> &CArcSome<ProcessList> {
         (&self.plist as *const CArcSome<c_void> as *const CArcSome<ProcessList>)
             .as_ref()
             .unwrap()
     }
+
+    unsafe fn mlist(&self) -> &CArcSome<KernelModuleList> {
+        (&self.mlist as *const CArcSome<c_void> as *const CArcSome<KernelModuleList>)
+            .as_ref()
+            .unwrap()
+    }
 }
 
 impl Branch for OsRoot {
@@ -117,7 +155,7 @@ impl Leaf for OsRoot {
 }
 
 impl StrBuild<CArc<Arc<MemflowBackend>>> for OsRoot {
-    fn build(input: &str, ctx: &CArc<Arc<MemflowBackend>>) -> Result<Self> {
+    fn build(input: &str, ctx: &CArc<Arc<MemflowBackend>>) -> BuildResult<Self> {
         let (chain_with, name, args) = split_args(input);
 
         let ctx = ctx.as_ref().ok_or(ErrorKind::NotFound)?;
@@ -126,7 +164,12 @@ impl StrBuild<CArc<Arc<MemflowBackend>>> for OsRoot {
             Some(
                 ctx.connector
                     .get(cw)
-                    .ok_or(ErrorKind::NotFound)?
+                    .ok_or_else(|| {
+                        BuildError::new(
+                            ErrorKind::NotFound,
+                            format!("connector/{cw} does not exist"),
+                        )
+                    })?
                     .get_orig()
                     .clone(),
             )
@@ -135,22 +178,43 @@ impl StrBuild<CArc<Arc<MemflowBackend>>> for OsRoot {
         };
 
         ctx.inventory
+            .read()
+            .unwrap()
             .create_os(
                 name,
                 chain_with,
-                Some(&str::parse(args).map_err(|_| ErrorKind::InvalidArgument)?),
+                Some(&str::parse(args).map_err(|e| {
+                    BuildError::new(
+                        ErrorKind::InvalidArgument,
+                        format!("invalid arguments: {e}"),
+                    )
+                })?),
             )
             .map(|c| ThreadedOs::from(c).self_arc_up())
-            // TODO: set ctx
-            .map(|c| OsBase {
-                os: c.into(),
-                ctx: Default::default(),
-            })
-            .map(Self::from)
-            .map_err(|_| ErrorKind::Uninitialized.into())
+            .map(|os| Self::new(OsBase::new(os), BuildSpec::new(input)))
+            .map_err(|e| build_error(e, format!("unable to create {name}")))
     }
 }
 
+extern "C" fn map_into_info(os: &OsRoot, ctx: &CArc<c_void>) -> COption<LeafArcBox<'static>> {
+    let file = FnFile::new(os.clone(), |os| {
+        let info = os.get_orig().info();
+        Ok(format!(
+            "plugin: {}\nbase: {:x}\nsize: {:x}\narch: {:?}\n",
+            os.spec().plugin,
+            info.base,
+            info.size,
+            info.arch
+        ))
+    });
+    COption::Some(trait_obj!((file, ctx.clone()) as Leaf))
+}
+
+extern "C" fn map_into_spec(os: &OsRoot, ctx: &CArc<c_void>) -> COption<LeafArcBox<'static>> {
+    let file = FnFile::new(os.spec.clone(), |spec| Ok(spec.to_string()));
+    COption::Some(trait_obj!((file, ctx.clone()) as Leaf))
+}
+
 impl ThreadedOs {
     extern "C" fn read(&self, data: VecOps<RWData>) -> i32 {
         int_res_wrap! {
@@ -560,3 +624,234 @@ impl Branch for PidNameProcessList {
         Ok(())
     }
 }
+
+/// Modules and drivers loaded in the kernel.
+#[derive(Clone)]
+struct KernelModuleList {
+    os: OsBase,
+    by_name: KernelModuleNameList,
+}
+
+impl From<OsBase> for KernelModuleList {
+    fn from(os: OsBase) -> Self {
+        Self {
+            by_name: os.clone().into(),
+            os,
+        }
+    }
+}
+
+impl KernelModuleList {
+    extern "C" fn map_into(os: &OsRoot, ctx: &CArc<c_void>) -> COption<BranchArcBox<'static>> {
+        COption::Some(trait_obj!(
+            (unsafe { &**os.mlist() }.clone(), ctx.clone()) as Branch
+        ))
+    }
+}
+
+fn kernel_module(os: &OsBase, info: ModuleInfo) -> ModuleArc {
+    ModuleArc::from(ModuleBase::new_kernel(os.os.clone(), info))
+}
+
+impl Branch for KernelModuleList {
+    fn get_entry(&self, path: &str, plugins: &CPluginStore) -> Result<DirEntry> {
+        let (entry, path) = branch::split_path(path);
+
+        match entry {
+            "by-name" => {
+                branch::forward_entry(self.by_name.clone(), self.os.ctx.clone(), path, plugins)
+            }
+            addr => {
+                let addr: umem =
+                    Num::from_str_radix(addr, 16).map_err(|_| ErrorKind::InvalidPath)?;
+
+                let info = self
+                    .os
+                    .get()
+                    .module_by_address(addr.into())
+                    .map_err(|_| ErrorKind::NotFound)?;
+
+                branch::forward_entry(
+                    kernel_module(&self.os, info),
+                    self.os.ctx.clone(),
+                    path,
+                    plugins,
+                )
+            }
+        }
+    }
+
+    fn list(
+        &self,
+        _plugins: &CPluginStore,
+        out: &mut OpaqueCallback<BranchListEntry>,
+    ) -> Result<()> {
+        let _ = out.call(BranchListEntry::new(
+            "by-name".into(),
+            DirEntry::Branch(trait_obj!(
+                (self.by_name.clone(), self.os.ctx.clone()) as Branch
+            )),
+        ));
+
+        self.os
+            .get()
+            .module_list_callback(
+                (&mut |info: ModuleInfo| {
+                    // The address of the module info, as `module_by_address` looks it up
+                    let addr = info.address.to_umem();
+                    let module = kernel_module(&self.os, info);
+                    let entry =
+                        DirEntry::Branch(trait_obj!((module, self.os.ctx.clone()) as Branch));
+                    out.call(BranchListEntry::new(format!("{:x}", addr).into(), entry))
+                })
+                    .into(),
+            )
+            .map_err(|_| ErrorKind::Unknown)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct KernelModuleNameList {
+    os: OsBase,
+    /// Replaced as a whole once listing the modules is done, so lookups meanwhile still hit it.
+    name_cache: CArcSome<RwLock<HashMap<String, Address>>>,
+}
+
+impl From<OsBase> for KernelModuleNameList {
+    fn from(os: OsBase) -> Self {
+        Self {
+            os,
+            name_cache: RwLock::default().into(),
+        }
+    }
+}
+
+impl KernelModuleNameList {
+    fn get_info(&self, name: &str) -> Result<ModuleInfo> {
+        let addr = self.name_cache.read().unwrap().get(name).copied();
+
+        // The module may have been unloaded, or its address reused since it got cached
+        let info = addr
+            .and_then(|addr| self.os.get().module_by_address(addr).ok())
+            .filter(|info| &*info.name == name);
+
+        if let Some(info) = info {
+            return Ok(info);
+        }
+
+        let info = self
+            .os
+            .get()
+            .module_by_name(name)
+            .map_err(|_| ErrorKind::NotFound)?;
+
+        self.name_cache
+            .write()
+            .unwrap()
+            .insert(name.into(), info.address);
+
+        Ok(info)
+    }
+}
+
+impl Branch for KernelModuleNameList {
+    fn get_entry(&self, path: &str, plugins: &CPluginStore) -> Result<DirEntry> {
+        let (name, path) = branch::split_path(path);
+
+        let module = kernel_module(&self.os, self.get_info(name)?);
+
+        branch::forward_entry(module, self.os.ctx.clone(), path, plugins)
+    }
+
+    fn list(&self, _: &CPluginStore, out: &mut OpaqueCallback<BranchListEntry>) -> Result<()> {
+        let mut name_cache = HashMap::new();
+
+        self.os
+            .get()
+            .module_list_callback(
+                (&mut |info: ModuleInfo| {
+                    let name = info.name.to_string();
+                    if name_cache.insert(name.clone(), info.address).is_none() {
+                        let module = kernel_module(&self.os, info);
+                        let entry =
+                            DirEntry::Branch(trait_obj!((module, self.os.ctx.clone()) as Branch));
+                        out.call(BranchListEntry::new(name.into(), entry))
+                    } else {
+                        true
+                    }
+                })
+                    .into(),
+            )
+            .map_err(|_| ErrorKind::Unknown)?;
+
+        *self.name_cache.write().unwrap() = name_cache;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memflow::dummy::{DummyMemory, DummyOs};
+
+    fn kernel_modules() -> KernelModuleList {
+        let os = memflow::plugins::os::create_instance(
+            DummyOs::new(DummyMemory::new(memflow::types::size::mb(1))),
+            CArc::default(),
+            &Default::default(),
+        );
+
+        OsBase::new(ThreadedOs::from(os).self_arc_up()).into()
+    }
+
+    fn error_kind(entry: Result<DirEntry>) -> Option<ErrorKind> {
+        entry.err().map(|Error(_, kind)| kind)
+    }
+
+    #[test]
+    pub fn module_by_address() {
+        let modules = kernel_modules();
+        let plugins = CPluginStore::default();
+
+        assert_eq!(
+            error_kind(modules.get_entry("not-hex", &plugins)),
+            Some(ErrorKind::InvalidPath)
+        );
+        assert_eq!(
+            error_kind(modules.get_entry("deadbeef/info", &plugins)),
+            Some(ErrorKind::NotFound)
+        );
+    }
+
+    /// Names of the entries listed by `branch`, other than `by-name`.
+    fn listed(branch: &impl Branch, plugins: &CPluginStore) -> Vec<String> {
+        let mut names = vec![];
+        let cb = &mut |entry: BranchListEntry| {
+            names.push(entry.name.to_string());
+            true
+        };
+        branch.list(plugins, &mut cb.into()).unwrap();
+        names.retain(|name| name != "by-name");
+        names
+    }
+
+    #[test]
+    pub fn listed_modules_round_trip() {
+        let modules = kernel_modules();
+        let plugins = CPluginStore::default();
+
+        for addr in listed(&modules, &plugins) {
+            let entry = modules.get_entry(&format!("{addr}/info"), &plugins);
+            assert!(entry.is_ok(), "{addr} is listed, but not found");
+        }
+
+        // Looked up through the cache filled by listing
+        for name in listed(&modules.by_name, &plugins) {
+            let entry = modules.by_name.get_entry(&format!("{name}/info"), &plugins);
+            assert!(entry.is_ok(), "{name} is listed, but not found");
+        }
+    }
+}
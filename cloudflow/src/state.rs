@@ -0,0 +1,167 @@
+//! State file recording the instances created through `new`, so that they can be restored after
+//! a restart.
+//!
+//! Every line holds the branch and the name of an instance, followed by the input it was built
+//! from, in the order the instances were created.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub branch: String,
+    pub name: String,
+    /// Input of `StrBuild::build`, which is what followed the name in `new`.
+    pub input: String,
+}
+
+impl Record {
+    fn parse(line: &str) -> Option<Self> {
+        let (branch, line) = line.split_once(' ')?;
+        let (name, input) = line.split_once(' ').unwrap_or((line, ""));
+
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            branch: branch.into(),
+            name: name.into(),
+            input: input.into(),
+        })
+    }
+}
+
+pub struct StateFile {
+    path: PathBuf,
+    records: Mutex<Vec<Record>>,
+}
+
+impl StateFile {
+    /// Read the records of the state file, which has none if it does not exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+
+        let records = match std::fs::read_to_string(&path) {
+            Ok(state) => state
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(i, line)| {
+                    Record::parse(line).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("malformed record on line {}", i + 1),
+                        )
+                    })
+                })
+                .collect::<io::Result<_>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            records: Mutex::new(records),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records in the order their instances were created.
+    pub fn records(&self) -> Vec<Record> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Record a new instance, replacing any previous record of the same name.
+    pub fn insert(&self, record: Record) -> io::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        records.retain(|r| r.branch != record.branch || r.name != record.name);
+        records.push(record);
+        self.save(&records)
+    }
+
+    pub fn remove(&self, branch: &str, name: &str) -> io::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        let len = records.len();
+        records.retain(|r| r.branch != branch || r.name != name);
+
+        if records.len() != len {
+            self.save(&records)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Replace the file as a whole, so that it is never left half written.
+    fn save(&self, records: &[Record]) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        let mut file = std::fs::File::create(&tmp)?;
+        for r in records {
+            writeln!(file, "{} {} {}", r.branch, r.name, r.input)?;
+        }
+        file.sync_all()?;
+
+        std::fs::rename(tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(branch: &str, name: &str, input: &str) -> Record {
+        Record {
+            branch: branch.into(),
+            name: name.into(),
+            input: input.into(),
+        }
+    }
+
+    #[test]
+    pub fn records_survive_reopening() {
+        let path = std::env::temp_dir().join(format!("cloudflow-state-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let state = StateFile::open(&path).unwrap();
+        assert!(state.records().is_empty());
+
+        state
+            .insert(record("connector", "vm", "qemu:my-vm"))
+            .unwrap();
+        state
+            .insert(record("os", "win", "-c vm win32::arch=x64"))
+            .unwrap();
+        state.insert(record("os", "lin", "-c vm linux")).unwrap();
+        state.remove("os", "lin").unwrap();
+
+        let records = StateFile::open(&path).unwrap().records();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            records,
+            vec![
+                record("connector", "vm", "qemu:my-vm"),
+                record("os", "win", "-c vm win32::arch=x64"),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn parse_record() {
+        assert_eq!(
+            Record::parse("os win win32"),
+            Some(record("os", "win", "win32"))
+        );
+        assert_eq!(
+            Record::parse("connector vm"),
+            Some(record("connector", "vm", ""))
+        );
+        assert_eq!(Record::parse("connector"), None);
+    }
+}
@@ -1,8 +1,11 @@
+use abi_stable::StableAbi;
 pub use cglue::slice::CSliceMut;
 
 use filer::prelude::v1::*;
 use memflow::prelude::v1::*;
 
+use std::fmt;
+
 /// Splits the connector/os arguments into parts.
 ///
 /// The parts provided are:
@@ -29,6 +32,71 @@ pub fn split_args(input: &str) -> (Option<&str>, &str, &str) {
     (chain_with, name, args)
 }
 
+/// Input an instance was built out of, split into its parts.
+#[repr(C)]
+#[derive(Clone, StableAbi)]
+pub struct BuildSpec {
+    pub chain_with: COption<ReprCString>,
+    pub plugin: ReprCString,
+    pub args: ReprCString,
+}
+
+impl BuildSpec {
+    pub fn new(input: &str) -> Self {
+        let (chain_with, plugin, args) = split_args(input);
+
+        Self {
+            chain_with: chain_with.map(ReprCString::from).into(),
+            plugin: plugin.into(),
+            args: args.into(),
+        }
+    }
+
+    /// Input building the same instance again, as written to `new` after the name.
+    pub fn input(&self) -> String {
+        let mut input = match &self.chain_with {
+            COption::Some(chain_with) => format!("-c {chain_with} {}", self.plugin),
+            COption::None => self.plugin.to_string(),
+        };
+
+        if !self.args.is_empty() {
+            input += ":";
+            input += &self.args;
+        }
+
+        input
+    }
+}
+
+impl fmt::Display for BuildSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "plugin: {}", self.plugin)?;
+        writeln!(f, "args: {}", self.args)?;
+
+        if let COption::Some(chain_with) = &self.chain_with {
+            writeln!(f, "chain_with: {chain_with}")?;
+        }
+
+        writeln!(f, "input: {}", self.input())
+    }
+}
+
+/// Describe a memflow error for the user, keeping the kinds filer knows about.
+pub fn build_error(err: memflow::error::Error, context: impl std::fmt::Display) -> BuildError {
+    use filer::error::ErrorKind;
+    use memflow::error::ErrorKind as MfErrorKind;
+
+    let kind = match err.1 {
+        MfErrorKind::PluginNotFound => ErrorKind::PluginNotFound,
+        MfErrorKind::InvalidArgument | MfErrorKind::Configuration => ErrorKind::InvalidArgument,
+        MfErrorKind::VersionMismatch => ErrorKind::VersionMismatch,
+        MfErrorKind::InvalidAbi => ErrorKind::InvalidAbi,
+        _ => ErrorKind::Uninitialized,
+    };
+
+    BuildError::new(kind, format!("{context}: {err}"))
+}
+
 pub fn memdata_map<B, F: FnOnce(MemOps<CTup3<Address, Address, B>, CTup2<Address, B>>) -> O, O>(
     VecOps { inp, out, out_fail }: VecOps<CTup2<Size, B>>,
     func: F,
@@ -54,3 +122,20 @@ pub fn memdata_map<B, F: FnOnce(MemOps<CTup3<Address, Address, B>, CTup2<Address
     let mut out_fail = out_fail.as_mut().map(<_>::into);
     MemOps::with_raw(inp, out.as_mut(), out_fail.as_mut(), func)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn build_spec() {
+        let spec = BuildSpec::new("-c vm win32:arch=x64\n");
+        assert_eq!(
+            spec.to_string(),
+            "plugin: win32\nargs: arch=x64\nchain_with: vm\ninput: -c vm win32:arch=x64\n"
+        );
+
+        let spec = BuildSpec::new("qemu");
+        assert_eq!(spec.to_string(), "plugin: qemu\nargs: \ninput: qemu\n");
+    }
+}
@@ -0,0 +1,942 @@
+//! 9P2000.L frontend to a filer `Node`.
+//!
+//! Unlike FUSE, this needs no privileges, nor a local kernel mount. Virtual machines, containers
+//! and remote machines can mount the node over virtio-9p or TCP, for instance with:
+//!
+//! ```text
+//! mount -t 9p -o trans=tcp,port=5640,version=9p2000.L,cache=none <host> /mnt/cloudflow
+//! ```
+//!
+//! Walks map to `metadata`, directory reads to `list`, and opening, reading, writing and clunking
+//! leaves to `open`, `read`, `write` and `close` of the node.
+
+use filer::prelude::v1::*;
+
+use filer_tokio::{Listener, SplitStream};
+
+use log::*;
+
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+use tokio::task::JoinHandle;
+
+pub mod proto;
+
+use proto::DirEntry as P9DirEntry;
+use proto::*;
+
+/// Upper bound for the message size negotiated with clients.
+pub const MAX_MSIZE: u32 = 1 << 20;
+
+/// Lower bound for the message size negotiated with clients.
+pub const MIN_MSIZE: u32 = 4096;
+
+/// Size of the `Rread` header preceding the data.
+const READ_HEADER_LEN: u32 = HEADER_LEN as u32 + 4;
+
+/// Block size reported in file and file system attributes.
+const BLOCK_SIZE: u32 = 4096;
+
+/// Magic of the 9P file system, as reported by `Rstatfs`.
+const V9FS_MAGIC: u32 = 0x01021997;
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+const O_ACCMODE: u32 = 0o3;
+const O_RDONLY: u32 = 0o0;
+
+/// Linux error numbers, as used by `Rlerror`.
+mod errno {
+    pub const ENOENT: u32 = 2;
+    pub const EIO: u32 = 5;
+    pub const EBADF: u32 = 9;
+    pub const EEXIST: u32 = 17;
+    pub const EISDIR: u32 = 21;
+    pub const EINVAL: u32 = 22;
+    pub const EROFS: u32 = 30;
+    pub const EOPNOTSUPP: u32 = 95;
+}
+
+/// Serves a `Node` to 9P2000.L clients connecting through the given listener.
+///
+/// Requests of a single client are processed one after another, on tokio's blocking thread pool.
+/// There is no authentication of any kind, so only listen on trusted interfaces, and consider
+/// making the server `read_only`.
+pub struct P9Server<T: Listener> {
+    listener: T,
+    clients: Vec<(JoinHandle<io::Result<()>>, T::SocketAddr)>,
+    node: CArcSome<Node>,
+    read_only: bool,
+}
+
+impl<T: Listener> P9Server<T> {
+    pub fn new(listener: T, node: CArcSome<Node>) -> Self {
+        Self {
+            listener,
+            clients: vec![],
+            node,
+            read_only: false,
+        }
+    }
+
+    /// Reject opening leaves for writing, and all writes.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub async fn run(mut self) -> io::Result<()> {
+        loop {
+            let (socket, addr) = self.listener.accept().await?;
+
+            self.clients.retain(|(h, _)| !h.is_finished());
+
+            let session = Session::new(self.node.clone(), self.read_only);
+            let handle = tokio::spawn(async move {
+                let ret = serve_client(session, socket).await;
+                if let Err(e) = &ret {
+                    debug!("9P client disconnected: {e}");
+                }
+                ret
+            });
+
+            self.clients.push((handle, addr));
+        }
+    }
+}
+
+async fn serve_client(mut session: Session, socket: impl SplitStream) -> io::Result<()> {
+    let (reader, writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+
+    let ret = loop {
+        let msg = match read_message(&mut reader, session.msize).await {
+            Ok(msg) => msg,
+            Err(e) => break Err(e),
+        };
+
+        let (tag, request) = match Tmessage::decode(&msg) {
+            Ok(decoded) => decoded,
+            Err(e) => break Err(e),
+        };
+
+        let (s, response) = tokio::task::spawn_blocking(move || {
+            let response = session.handle(request);
+            (session, response)
+        })
+        .await?;
+
+        session = s;
+
+        if let Err(e) = writer.write_all(&response.encode(tag)).await {
+            break Err(e);
+        }
+
+        if let Err(e) = writer.flush().await {
+            break Err(e);
+        }
+    };
+
+    tokio::task::spawn_blocking(move || session.clunk_all()).await?;
+
+    // A client hanging up between messages is a regular disconnect
+    match ret {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(()),
+        ret => ret,
+    }
+}
+
+/// Failure of a request, as a Linux error number.
+struct Errno(u32);
+
+impl From<Error> for Errno {
+    fn from(Error(_, kind): Error) -> Self {
+        Self(match kind {
+            ErrorKind::NotFound | ErrorKind::InvalidPath | ErrorKind::PluginNotFound => {
+                errno::ENOENT
+            }
+            ErrorKind::AlreadyExists => errno::EEXIST,
+            ErrorKind::ReadOnly => errno::EROFS,
+            ErrorKind::NotSupported | ErrorKind::NotImplemented => errno::EOPNOTSUPP,
+            ErrorKind::InvalidArgument => errno::EINVAL,
+            _ => errno::EIO,
+        })
+    }
+}
+
+type P9Result<T> = std::result::Result<T, Errno>;
+
+enum Open {
+    /// Handle of the leaf, and whether it was opened for writing.
+    Leaf { handle: usize, writable: bool },
+    /// Entries of the branch, listed when it was opened.
+    Branch(Vec<P9DirEntry>),
+}
+
+/// Path within the node a fid refers to, and the way it is open.
+struct Fid {
+    path: String,
+    is_branch: bool,
+    open: Option<Open>,
+}
+
+/// State of a single client connection.
+struct Session {
+    node: CArcSome<Node>,
+    read_only: bool,
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Session {
+    fn new(node: CArcSome<Node>, read_only: bool) -> Self {
+        Self {
+            node,
+            read_only,
+            msize: MAX_MSIZE,
+            fids: Default::default(),
+        }
+    }
+
+    fn handle(&mut self, request: Tmessage) -> Rmessage {
+        self.dispatch(request)
+            .unwrap_or_else(|Errno(ecode)| Rmessage::Lerror { ecode })
+    }
+
+    fn dispatch(&mut self, request: Tmessage) -> P9Result<Rmessage> {
+        match request {
+            Tmessage::Version { msize, version } => {
+                self.clunk_all();
+                if msize < MIN_MSIZE {
+                    return Err(Errno(errno::EINVAL));
+                }
+                self.msize = std::cmp::min(msize, MAX_MSIZE);
+                let version = if version.starts_with(VERSION) {
+                    VERSION
+                } else {
+                    "unknown"
+                };
+                Ok(Rmessage::Version {
+                    msize: self.msize,
+                    version: version.into(),
+                })
+            }
+            Tmessage::Attach { fid, .. } => {
+                let meta = self.node.metadata("")?;
+                self.insert(fid, Fid::new(String::new(), meta.is_branch))?;
+                Ok(Rmessage::Attach {
+                    qid: qid("", meta.is_branch),
+                })
+            }
+            Tmessage::Walk {
+                fid,
+                newfid,
+                wnames,
+            } => self.walk(fid, newfid, wnames),
+            Tmessage::Getattr { fid, .. } => self.getattr(fid),
+            Tmessage::Lopen { fid, flags } => self.lopen(fid, flags),
+            Tmessage::Readdir { fid, offset, count } => self.readdir(fid, offset, count),
+            Tmessage::Read { fid, offset, count } => self.read(fid, offset, count),
+            Tmessage::Write { fid, offset, data } => self.write(fid, offset, &data),
+            Tmessage::Clunk { fid } => {
+                let fid = self.fids.remove(&fid).ok_or(Errno(errno::EBADF))?;
+                if let Some(Open::Leaf { handle, .. }) = fid.open {
+                    self.node.close(handle)?;
+                }
+                Ok(Rmessage::Clunk)
+            }
+            // Requests are processed in order, so there is never anything left to flush
+            Tmessage::Flush { .. } => Ok(Rmessage::Flush),
+            Tmessage::Statfs { fid } => {
+                self.fid(fid)?;
+                Ok(Rmessage::Statfs(Statfs {
+                    ty: V9FS_MAGIC,
+                    bsize: BLOCK_SIZE,
+                    namelen: 255,
+                    ..Default::default()
+                }))
+            }
+            Tmessage::Fsync { fid } => {
+                self.fid(fid)?;
+                Ok(Rmessage::Fsync)
+            }
+            Tmessage::Other(ty) => {
+                trace!("unsupported 9P request {ty}");
+                Err(Errno(errno::EOPNOTSUPP))
+            }
+        }
+    }
+
+    fn fid(&self, fid: u32) -> P9Result<&Fid> {
+        self.fids.get(&fid).ok_or(Errno(errno::EBADF))
+    }
+
+    fn insert(&mut self, fid: u32, entry: Fid) -> P9Result<()> {
+        if self.fids.contains_key(&fid) {
+            return Err(Errno(errno::EBADF));
+        }
+        self.fids.insert(fid, entry);
+        Ok(())
+    }
+
+    fn walk(&mut self, fid: u32, newfid: u32, wnames: Vec<String>) -> P9Result<Rmessage> {
+        let base = self.fid(fid)?;
+
+        if base.open.is_some() || (newfid != fid && self.fids.contains_key(&newfid)) {
+            return Err(Errno(errno::EBADF));
+        }
+
+        let mut path = base.path.clone();
+        let mut is_branch = base.is_branch;
+        let mut qids = vec![];
+
+        for name in &wnames {
+            let next = match name.as_str() {
+                ".." => path.rsplit_once('/').map_or("", |(p, _)| p).to_string(),
+                "" | "." => return Err(Errno(errno::EINVAL)),
+                name if name.contains('/') => return Err(Errno(errno::EINVAL)),
+                name if path.is_empty() => name.to_string(),
+                name => format!("{path}/{name}"),
+            };
+
+            match self.node.metadata(&next) {
+                Ok(meta) => {
+                    qids.push(qid(&next, meta.is_branch));
+                    path = next;
+                    is_branch = meta.is_branch;
+                }
+                Err(e) if qids.is_empty() => return Err(e.into()),
+                // Partial walks are successful, but do not create the new fid
+                Err(_) => return Ok(Rmessage::Walk { qids }),
+            }
+        }
+
+        self.fids.insert(newfid, Fid::new(path, is_branch));
+
+        Ok(Rmessage::Walk { qids })
+    }
+
+    fn getattr(&self, fid: u32) -> P9Result<Rmessage> {
+        let fid = self.fid(fid)?;
+        let meta = self.node.metadata(&fid.path)?;
+
+        let mode = if meta.is_branch {
+            S_IFDIR | if self.read_only { 0o555 } else { 0o755 }
+        } else {
+            let has_write = meta.has_write && !self.read_only;
+            S_IFREG | (0o200 * has_write as u32) | (0o444 * meta.has_read as u32)
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let now = Time {
+            sec: now.as_secs(),
+            nsec: now.subsec_nanos() as u64,
+        };
+
+        Ok(Rmessage::Getattr(Attr {
+            valid: GETATTR_BASIC,
+            qid: qid(&fid.path, meta.is_branch),
+            mode,
+            nlink: 1,
+            size: meta.size,
+            blksize: BLOCK_SIZE as u64,
+            blocks: meta.size.div_ceil(512),
+            atime: now,
+            mtime: now,
+            ctime: now,
+            ..Default::default()
+        }))
+    }
+
+    fn lopen(&mut self, fid: u32, flags: u32) -> P9Result<Rmessage> {
+        let entry = self.fids.get_mut(&fid).ok_or(Errno(errno::EBADF))?;
+
+        if entry.open.is_some() {
+            return Err(Errno(errno::EINVAL));
+        }
+
+        let writes = flags & O_ACCMODE != O_RDONLY;
+
+        let open = if entry.is_branch {
+            if writes {
+                return Err(Errno(errno::EISDIR));
+            }
+            Open::Branch(list(&self.node, &entry.path)?)
+        } else {
+            if writes && self.read_only {
+                return Err(Errno(errno::EROFS));
+            }
+            Open::Leaf {
+                handle: self.node.open(&entry.path)?,
+                writable: writes,
+            }
+        };
+
+        entry.open = Some(open);
+
+        Ok(Rmessage::Lopen {
+            qid: qid(&entry.path, entry.is_branch),
+            iounit: self.msize - READ_HEADER_LEN,
+        })
+    }
+
+    fn readdir(&self, fid: u32, offset: u64, count: u32) -> P9Result<Rmessage> {
+        let entries = match &self.fid(fid)?.open {
+            Some(Open::Branch(entries)) => entries,
+            _ => return Err(Errno(errno::EBADF)),
+        };
+
+        let count = std::cmp::min(count, self.msize - READ_HEADER_LEN) as usize;
+        let mut len = 0;
+
+        let entries = entries
+            .iter()
+            .skip(offset as usize)
+            .take_while(|entry| {
+                len += entry.encoded_len();
+                len <= count
+            })
+            .cloned()
+            .collect();
+
+        Ok(Rmessage::Readdir { entries })
+    }
+
+    fn read(&self, fid: u32, offset: u64, count: u32) -> P9Result<Rmessage> {
+        let handle = self.leaf(fid)?;
+
+        let count = std::cmp::min(count, self.msize - READ_HEADER_LEN);
+        let mut data = vec![0; count as usize];
+
+        let mut cursor = ObjCursor::from((&self.node, handle, false));
+        cursor
+            .seek(SeekFrom::Start(offset))
+            .map_err(|_| Errno(errno::EIO))?;
+
+        match cursor.read(&mut data) {
+            Ok(read) => data.truncate(read),
+            // Nothing could be read at all, which is the end of the leaf
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => data.clear(),
+            Err(_) => return Err(Errno(errno::EIO)),
+        }
+
+        Ok(Rmessage::Read { data })
+    }
+
+    fn write(&self, fid: u32, offset: u64, data: &[u8]) -> P9Result<Rmessage> {
+        let handle = self.leaf(fid)?;
+
+        if self.read_only {
+            return Err(Errno(errno::EROFS));
+        }
+
+        // Like regular files, leaves opened read-only can not be written through
+        if !matches!(self.fid(fid)?.open, Some(Open::Leaf { writable: true, .. })) {
+            return Err(Errno(errno::EBADF));
+        }
+
+        let mut cursor = ObjCursor::from((&self.node, handle, false));
+        cursor
+            .seek(SeekFrom::Start(offset))
+            .map_err(|_| Errno(errno::EIO))?;
+
+        let count = cursor.write(data).map_err(|e| {
+            // The node gives a reason when nothing could be written
+            e.get_ref()
+                .and_then(|e| e.downcast_ref::<Error>())
+                .map_or(Errno(errno::EIO), |&e| e.into())
+        })?;
+
+        Ok(Rmessage::Write {
+            count: count as u32,
+        })
+    }
+
+    /// Get the handle of the open leaf `fid` refers to.
+    fn leaf(&self, fid: u32) -> P9Result<usize> {
+        match self.fid(fid)?.open {
+            Some(Open::Leaf { handle, .. }) => Ok(handle),
+            Some(Open::Branch(_)) => Err(Errno(errno::EISDIR)),
+            None => Err(Errno(errno::EBADF)),
+        }
+    }
+
+    /// Forget all fids, closing the handles of open leaves.
+    fn clunk_all(&mut self) {
+        for (_, fid) in self.fids.drain() {
+            if let Some(Open::Leaf { handle, .. }) = fid.open {
+                self.node.close(handle).ok();
+            }
+        }
+    }
+}
+
+impl Fid {
+    fn new(path: String, is_branch: bool) -> Self {
+        Self {
+            path,
+            is_branch,
+            open: None,
+        }
+    }
+}
+
+/// Build the qid of a path. Its identity is the FNV-1a hash of the path.
+fn qid(path: &str, is_branch: bool) -> Qid {
+    let path = path.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+
+    Qid {
+        ty: if is_branch { QTDIR } else { QTFILE },
+        version: 0,
+        path,
+    }
+}
+
+/// List a branch as directory entries. Offsets of the entries are their 1-based indices.
+fn list(node: &CArcSome<Node>, path: &str) -> Result<Vec<P9DirEntry>> {
+    let mut entries = vec![];
+
+    let cb = &mut |entry: ListEntry| {
+        let name = String::from(&*entry.name);
+        let full = if path.is_empty() {
+            name.clone()
+        } else {
+            format!("{path}/{name}")
+        };
+
+        entries.push(P9DirEntry {
+            qid: qid(&full, entry.is_branch),
+            offset: entries.len() as u64 + 1,
+            ty: if entry.is_branch { DT_DIR } else { DT_REG },
+            name,
+        });
+        true
+    };
+
+    node.list(path, &mut cb.into())?;
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cglue::tuple::CTup2;
+    use filer_tokio::{duplex_listener, DuplexConnector};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::DuplexStream;
+    use tokio::runtime::Runtime;
+
+    /// In-memory backend with a single readable and writeable leaf.
+    #[derive(Default)]
+    struct BufBackend {
+        data: Mutex<Vec<u8>>,
+        opened: Arc<AtomicUsize>,
+    }
+
+    impl Backend for BufBackend {
+        fn read(&self, _: BackendStack, _: usize, mut data: VecOps<RWData>) -> Result<()> {
+            let buf = self.data.lock().unwrap();
+            for CTup2(off, mut to) in data.inp {
+                let (off, len) = (off as usize, to.len());
+                if off + len <= buf.len() {
+                    to.copy_from_slice(&buf[off..(off + len)]);
+                    opt_call(data.out.as_deref_mut(), CTup2(off as Size, to));
+                } else {
+                    let e = Error(ErrorOrigin::Read, ErrorKind::OutOfBounds);
+                    opt_call(
+                        data.out_fail.as_deref_mut(),
+                        (CTup2(off as Size, to), e).into(),
+                    );
+                }
+            }
+            Ok(())
+        }
+
+        fn write(&self, _: BackendStack, _: usize, mut data: VecOps<ROData>) -> Result<()> {
+            let mut buf = self.data.lock().unwrap();
+            for CTup2(off, from) in data.inp {
+                let off = off as usize;
+                if buf.len() < off + from.len() {
+                    buf.resize(off + from.len(), 0);
+                }
+                buf[off..(off + from.len())].copy_from_slice(&from);
+                opt_call(data.out.as_deref_mut(), CTup2(off as Size, from));
+            }
+            Ok(())
+        }
+
+        fn rpc(&self, _: BackendStack, _: usize, _: &[u8], _: &mut [u8]) -> Result<()> {
+            Err(Error(ErrorOrigin::Backend, ErrorKind::NotSupported))
+        }
+
+        fn close(&self, _: BackendStack, _: usize) -> Result<()> {
+            self.opened.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn open(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<usize> {
+            if path == "buf" {
+                self.opened.fetch_add(1, Ordering::SeqCst);
+                Ok(0)
+            } else {
+                Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound))
+            }
+        }
+
+        fn metadata(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<NodeMetadata> {
+            match path {
+                "" => Ok(NodeMetadata::branch()),
+                "buf" => Ok(NodeMetadata {
+                    has_read: true,
+                    has_write: true,
+                    size: self.data.lock().unwrap().len() as Size,
+                    ..Default::default()
+                }),
+                _ => Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+            }
+        }
+
+        fn list(
+            &self,
+            _: BackendStack,
+            _: &str,
+            _: &CPluginStore,
+            out: &mut OpaqueCallback<ListEntry>,
+        ) -> Result<()> {
+            let _ = out.call(ListEntry::new("buf".into(), false));
+            Ok(())
+        }
+    }
+
+    /// Minimal 9P client issuing one request at a time.
+    struct Client {
+        stream: DuplexStream,
+        tag: u16,
+    }
+
+    impl Client {
+        async fn connect(connector: &DuplexConnector) -> Self {
+            let mut client = Self {
+                stream: connector.connect().unwrap(),
+                tag: 0,
+            };
+
+            let version = Tmessage::Version {
+                msize: 8192,
+                version: VERSION.into(),
+            };
+            assert_eq!(
+                client.call_tagged(NOTAG, version).await,
+                Rmessage::Version {
+                    msize: 8192,
+                    version: VERSION.into()
+                }
+            );
+
+            let attach = Tmessage::Attach {
+                fid: 0,
+                afid: NOFID,
+                uname: "user".into(),
+                aname: String::new(),
+                n_uname: 1000,
+            };
+            assert!(matches!(
+                client.call(attach).await,
+                Rmessage::Attach { qid } if qid.ty == QTDIR
+            ));
+
+            client
+        }
+
+        async fn call(&mut self, request: Tmessage) -> Rmessage {
+            self.tag += 1;
+            self.call_tagged(self.tag, request).await
+        }
+
+        async fn call_tagged(&mut self, tag: u16, request: Tmessage) -> Rmessage {
+            self.stream.write_all(&request.encode(tag)).await.unwrap();
+            let msg = read_message(&mut self.stream, MAX_MSIZE).await.unwrap();
+            let (rtag, response) = Rmessage::decode(&msg).unwrap();
+            assert_eq!(rtag, tag);
+            response
+        }
+
+        /// Walk from the root to `path`, and open it with `flags`.
+        async fn open(&mut self, fid: u32, path: &str, flags: u32) -> Rmessage {
+            let walk = Tmessage::Walk {
+                fid: 0,
+                newfid: fid,
+                wnames: vec![path.into()],
+            };
+            match self.call(walk).await {
+                Rmessage::Walk { .. } => self.call(Tmessage::Lopen { fid, flags }).await,
+                err => err,
+            }
+        }
+    }
+
+    fn serve(read_only: bool) -> (Runtime, DuplexConnector, Arc<AtomicUsize>) {
+        let runtime = Runtime::new().unwrap();
+        let (listener, connector) = duplex_listener(1 << 16);
+
+        let backend = BufBackend::default();
+        let opened = backend.opened.clone();
+
+        let mut server = P9Server::new(listener, Node::new(backend).into());
+        if read_only {
+            server = server.read_only();
+        }
+        runtime.spawn(server.run());
+
+        (runtime, connector, opened)
+    }
+
+    #[test]
+    pub fn p9_walk_readdir() {
+        let (runtime, connector, _) = serve(false);
+
+        runtime.block_on(async {
+            let mut client = Client::connect(&connector).await;
+
+            let walk = Tmessage::Walk {
+                fid: 0,
+                newfid: 1,
+                wnames: vec!["buf".into()],
+            };
+            let qids = match client.call(walk).await {
+                Rmessage::Walk { qids } => qids,
+                r => panic!("{r:?}"),
+            };
+            assert_eq!(qids, vec![qid("buf", false)]);
+
+            match client
+                .call(Tmessage::Getattr {
+                    fid: 1,
+                    request_mask: GETATTR_BASIC,
+                })
+                .await
+            {
+                Rmessage::Getattr(attr) => {
+                    assert_eq!(attr.mode, S_IFREG | 0o644);
+                    assert_eq!(attr.size, 0);
+                }
+                r => panic!("{r:?}"),
+            }
+
+            let walk = Tmessage::Walk {
+                fid: 0,
+                newfid: 2,
+                wnames: vec!["missing".into()],
+            };
+            assert_eq!(
+                client.call(walk).await,
+                Rmessage::Lerror {
+                    ecode: errno::ENOENT
+                }
+            );
+
+            // Only the first element resolves, so the new fid is not created
+            let walk = Tmessage::Walk {
+                fid: 0,
+                newfid: 2,
+                wnames: vec!["buf".into(), "missing".into()],
+            };
+            assert_eq!(client.call(walk).await, Rmessage::Walk { qids });
+            assert_eq!(
+                client.call(Tmessage::Clunk { fid: 2 }).await,
+                Rmessage::Lerror {
+                    ecode: errno::EBADF
+                }
+            );
+
+            let walk = Tmessage::Walk {
+                fid: 0,
+                newfid: 2,
+                wnames: vec![],
+            };
+            assert_eq!(client.call(walk).await, Rmessage::Walk { qids: vec![] });
+            assert!(matches!(
+                client.call(Tmessage::Lopen { fid: 2, flags: 0 }).await,
+                Rmessage::Lopen { qid, .. } if qid.ty == QTDIR
+            ));
+
+            let readdir = Tmessage::Readdir {
+                fid: 2,
+                offset: 0,
+                count: 4096,
+            };
+            match client.call(readdir).await {
+                Rmessage::Readdir { entries } => {
+                    assert_eq!(entries.len(), 1);
+                    assert_eq!(entries[0].name, "buf");
+                    assert_eq!(entries[0].ty, DT_REG);
+                    assert_eq!(entries[0].offset, 1);
+                }
+                r => panic!("{r:?}"),
+            }
+
+            let readdir = Tmessage::Readdir {
+                fid: 2,
+                offset: 1,
+                count: 4096,
+            };
+            assert_eq!(
+                client.call(readdir).await,
+                Rmessage::Readdir { entries: vec![] }
+            );
+
+            assert_eq!(
+                client.call(Tmessage::Other(76)).await,
+                Rmessage::Lerror {
+                    ecode: errno::EOPNOTSUPP
+                }
+            );
+        });
+    }
+
+    #[test]
+    pub fn p9_read_write() {
+        let (runtime, connector, opened) = serve(false);
+
+        runtime.block_on(async {
+            let mut client = Client::connect(&connector).await;
+
+            assert!(matches!(
+                client.open(1, "buf", 2).await,
+                Rmessage::Lopen { qid, iounit } if qid.ty == QTFILE && iounit == 8192 - 11
+            ));
+            assert_eq!(opened.load(Ordering::SeqCst), 1);
+
+            let write = Tmessage::Write {
+                fid: 1,
+                offset: 4,
+                data: b"hello".to_vec(),
+            };
+            assert_eq!(client.call(write).await, Rmessage::Write { count: 5 });
+
+            let read = Tmessage::Read {
+                fid: 1,
+                offset: 0,
+                count: 9,
+            };
+            assert_eq!(
+                client.call(read).await,
+                Rmessage::Read {
+                    data: b"\0\0\0\0hello".to_vec()
+                }
+            );
+
+            // Reading past the end yields no data
+            let read = Tmessage::Read {
+                fid: 1,
+                offset: 9,
+                count: 16,
+            };
+            assert_eq!(client.call(read).await, Rmessage::Read { data: vec![] });
+
+            assert_eq!(
+                client.call(Tmessage::Clunk { fid: 1 }).await,
+                Rmessage::Clunk
+            );
+            assert_eq!(opened.load(Ordering::SeqCst), 0);
+
+            // Handles left open get closed once the client disconnects
+            client.open(1, "buf", 0).await;
+            assert_eq!(opened.load(Ordering::SeqCst), 1);
+            drop(client);
+
+            while opened.load(Ordering::SeqCst) != 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        });
+    }
+
+    #[test]
+    pub fn p9_read_only() {
+        let (runtime, connector, opened) = serve(true);
+
+        runtime.block_on(async {
+            let mut client = Client::connect(&connector).await;
+
+            assert_eq!(
+                client.open(1, "buf", 1).await,
+                Rmessage::Lerror {
+                    ecode: errno::EROFS
+                }
+            );
+            assert_eq!(opened.load(Ordering::SeqCst), 0);
+
+            assert!(matches!(
+                client.open(2, "buf", 0).await,
+                Rmessage::Lopen { .. }
+            ));
+
+            let write = Tmessage::Write {
+                fid: 2,
+                offset: 0,
+                data: b"hello".to_vec(),
+            };
+            assert_eq!(
+                client.call(write).await,
+                Rmessage::Lerror {
+                    ecode: errno::EROFS
+                }
+            );
+
+            match client
+                .call(Tmessage::Getattr {
+                    fid: 0,
+                    request_mask: GETATTR_BASIC,
+                })
+                .await
+            {
+                Rmessage::Getattr(attr) => assert_eq!(attr.mode, S_IFDIR | 0o555),
+                r => panic!("{r:?}"),
+            }
+        });
+    }
+
+    #[test]
+    pub fn p9_write_read_only_fid() {
+        let (runtime, connector, _) = serve(false);
+
+        runtime.block_on(async {
+            let mut client = Client::connect(&connector).await;
+
+            assert!(matches!(
+                client.open(1, "buf", O_RDONLY).await,
+                Rmessage::Lopen { .. }
+            ));
+
+            let write = Tmessage::Write {
+                fid: 1,
+                offset: 0,
+                data: b"hello".to_vec(),
+            };
+            assert_eq!(
+                client.call(write).await,
+                Rmessage::Lerror {
+                    ecode: errno::EBADF
+                }
+            );
+
+            let read = Tmessage::Read {
+                fid: 1,
+                offset: 0,
+                count: 16,
+            };
+            assert_eq!(client.call(read).await, Rmessage::Read { data: vec![] });
+        });
+    }
+}
@@ -0,0 +1,685 @@
+//! Wire format of the 9P2000.L protocol.
+//!
+//! Every message has the form `[size: u32][type: u8][tag: u16][body]`, with `size` covering the
+//! whole message. All integers are little endian, strings are prefixed by their `u16` length.
+//!
+//! Only the subset of messages needed for serving a read-mostly tree is decoded. Anything else
+//! shows up as `Tmessage::Other`, and gets answered with an error.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The only protocol version spoken.
+pub const VERSION: &str = "9P2000.L";
+
+/// Tag of `Tversion` messages.
+pub const NOTAG: u16 = !0;
+
+/// Fid standing for no fid at all, for instance as the `afid` of an unauthenticated `Tattach`.
+pub const NOFID: u32 = !0;
+
+/// Size of the `[size][type][tag]` header of every message.
+pub const HEADER_LEN: usize = 7;
+
+/// Qid type of directories.
+pub const QTDIR: u8 = 0x80;
+/// Qid type of regular files.
+pub const QTFILE: u8 = 0;
+
+/// Directory entry type of directories.
+pub const DT_DIR: u8 = 4;
+/// Directory entry type of regular files.
+pub const DT_REG: u8 = 8;
+
+/// All the basic fields of `Rgetattr` are valid.
+pub const GETATTR_BASIC: u64 = 0x7ff;
+
+const RLERROR: u8 = 7;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TGETATTR: u8 = 24;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TFLUSH: u8 = 108;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+
+/// Unique identity of a file on the server.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Qid {
+    pub ty: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+/// A point in time, as seconds and nanoseconds since the epoch.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Time {
+    pub sec: u64,
+    pub nsec: u64,
+}
+
+/// Attributes of a file, as returned by `Rgetattr`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Attr {
+    pub valid: u64,
+    pub qid: Qid,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u64,
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    pub atime: Time,
+    pub mtime: Time,
+    pub ctime: Time,
+    pub btime: Time,
+    pub gen: u64,
+    pub data_version: u64,
+}
+
+/// Attributes of the file system, as returned by `Rstatfs`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Statfs {
+    pub ty: u32,
+    pub bsize: u32,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub fsid: u64,
+    pub namelen: u32,
+}
+
+/// Single entry of `Rreaddir`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirEntry {
+    pub qid: Qid,
+    /// Offset to continue reading the directory from after this entry.
+    pub offset: u64,
+    pub ty: u8,
+    pub name: String,
+}
+
+impl DirEntry {
+    /// Size of the encoded entry.
+    pub fn encoded_len(&self) -> usize {
+        13 + 8 + 1 + 2 + self.name.len()
+    }
+}
+
+/// Requests sent by the client.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Tmessage {
+    Version {
+        msize: u32,
+        version: String,
+    },
+    Attach {
+        fid: u32,
+        afid: u32,
+        uname: String,
+        aname: String,
+        n_uname: u32,
+    },
+    Walk {
+        fid: u32,
+        newfid: u32,
+        wnames: Vec<String>,
+    },
+    Getattr {
+        fid: u32,
+        request_mask: u64,
+    },
+    Lopen {
+        fid: u32,
+        flags: u32,
+    },
+    Readdir {
+        fid: u32,
+        offset: u64,
+        count: u32,
+    },
+    Read {
+        fid: u32,
+        offset: u64,
+        count: u32,
+    },
+    Write {
+        fid: u32,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    Clunk {
+        fid: u32,
+    },
+    Flush {
+        oldtag: u16,
+    },
+    Statfs {
+        fid: u32,
+    },
+    Fsync {
+        fid: u32,
+    },
+    /// Request of the given type that is not supported.
+    Other(u8),
+}
+
+/// Responses sent by the server.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Rmessage {
+    /// Failure of any request, carrying a Linux `errno` value.
+    Lerror {
+        ecode: u32,
+    },
+    Version {
+        msize: u32,
+        version: String,
+    },
+    Attach {
+        qid: Qid,
+    },
+    Walk {
+        qids: Vec<Qid>,
+    },
+    Getattr(Attr),
+    Lopen {
+        qid: Qid,
+        iounit: u32,
+    },
+    Readdir {
+        entries: Vec<DirEntry>,
+    },
+    Read {
+        data: Vec<u8>,
+    },
+    Write {
+        count: u32,
+    },
+    Clunk,
+    Flush,
+    Statfs(Statfs),
+    Fsync,
+}
+
+impl Tmessage {
+    pub fn encode(&self, tag: u16) -> Vec<u8> {
+        use Tmessage::*;
+
+        let (ty, w) = match self {
+            Version { msize, version } => (TVERSION, Writer::new().u32(*msize).str(version)),
+            Attach {
+                fid,
+                afid,
+                uname,
+                aname,
+                n_uname,
+            } => (
+                TATTACH,
+                Writer::new()
+                    .u32(*fid)
+                    .u32(*afid)
+                    .str(uname)
+                    .str(aname)
+                    .u32(*n_uname),
+            ),
+            Walk {
+                fid,
+                newfid,
+                wnames,
+            } => {
+                let mut w = Writer::new()
+                    .u32(*fid)
+                    .u32(*newfid)
+                    .u16(wnames.len() as u16);
+                for name in wnames {
+                    w = w.str(name);
+                }
+                (TWALK, w)
+            }
+            Getattr { fid, request_mask } => (TGETATTR, Writer::new().u32(*fid).u64(*request_mask)),
+            Lopen { fid, flags } => (TLOPEN, Writer::new().u32(*fid).u32(*flags)),
+            Readdir { fid, offset, count } => {
+                (TREADDIR, Writer::new().u32(*fid).u64(*offset).u32(*count))
+            }
+            Read { fid, offset, count } => {
+                (TREAD, Writer::new().u32(*fid).u64(*offset).u32(*count))
+            }
+            Write { fid, offset, data } => (
+                TWRITE,
+                Writer::new()
+                    .u32(*fid)
+                    .u64(*offset)
+                    .u32(data.len() as u32)
+                    .bytes(data),
+            ),
+            Clunk { fid } => (TCLUNK, Writer::new().u32(*fid)),
+            Flush { oldtag } => (TFLUSH, Writer::new().u16(*oldtag)),
+            Statfs { fid } => (TSTATFS, Writer::new().u32(*fid)),
+            Fsync { fid } => (TFSYNC, Writer::new().u32(*fid).u32(0)),
+            Other(ty) => (*ty, Writer::new()),
+        };
+
+        w.finish(ty, tag)
+    }
+
+    /// Decode a whole message, as returned by `read_message`.
+    pub fn decode(msg: &[u8]) -> io::Result<(u16, Self)> {
+        use Tmessage::*;
+
+        let (ty, tag, mut r) = Reader::header(msg)?;
+
+        let ret = match ty {
+            TVERSION => Version {
+                msize: r.u32()?,
+                version: r.str()?,
+            },
+            TATTACH => Attach {
+                fid: r.u32()?,
+                afid: r.u32()?,
+                uname: r.str()?,
+                aname: r.str()?,
+                n_uname: r.u32()?,
+            },
+            TWALK => {
+                let fid = r.u32()?;
+                let newfid = r.u32()?;
+                let wnames = (0..r.u16()?).map(|_| r.str()).collect::<io::Result<_>>()?;
+                Walk {
+                    fid,
+                    newfid,
+                    wnames,
+                }
+            }
+            TGETATTR => Getattr {
+                fid: r.u32()?,
+                request_mask: r.u64()?,
+            },
+            TLOPEN => Lopen {
+                fid: r.u32()?,
+                flags: r.u32()?,
+            },
+            TREADDIR => Readdir {
+                fid: r.u32()?,
+                offset: r.u64()?,
+                count: r.u32()?,
+            },
+            TREAD => Read {
+                fid: r.u32()?,
+                offset: r.u64()?,
+                count: r.u32()?,
+            },
+            TWRITE => {
+                let fid = r.u32()?;
+                let offset = r.u64()?;
+                let count = r.u32()? as usize;
+                Write {
+                    fid,
+                    offset,
+                    data: r.bytes(count)?.to_vec(),
+                }
+            }
+            TCLUNK => Clunk { fid: r.u32()? },
+            TFLUSH => Flush { oldtag: r.u16()? },
+            TSTATFS => Statfs { fid: r.u32()? },
+            // Older kernels do not send the datasync flag
+            TFSYNC => Fsync { fid: r.u32()? },
+            ty => Other(ty),
+        };
+
+        Ok((tag, ret))
+    }
+}
+
+impl Rmessage {
+    pub fn encode(&self, tag: u16) -> Vec<u8> {
+        use Rmessage::*;
+
+        let (ty, w) = match self {
+            Lerror { ecode } => (RLERROR, Writer::new().u32(*ecode)),
+            Version { msize, version } => (TVERSION + 1, Writer::new().u32(*msize).str(version)),
+            Attach { qid } => (TATTACH + 1, Writer::new().qid(qid)),
+            Walk { qids } => {
+                let mut w = Writer::new().u16(qids.len() as u16);
+                for qid in qids {
+                    w = w.qid(qid);
+                }
+                (TWALK + 1, w)
+            }
+            Getattr(attr) => {
+                let mut w = Writer::new()
+                    .u64(attr.valid)
+                    .qid(&attr.qid)
+                    .u32(attr.mode)
+                    .u32(attr.uid)
+                    .u32(attr.gid)
+                    .u64(attr.nlink)
+                    .u64(attr.rdev)
+                    .u64(attr.size)
+                    .u64(attr.blksize)
+                    .u64(attr.blocks);
+                for time in [attr.atime, attr.mtime, attr.ctime, attr.btime] {
+                    w = w.u64(time.sec).u64(time.nsec);
+                }
+                (TGETATTR + 1, w.u64(attr.gen).u64(attr.data_version))
+            }
+            Lopen { qid, iounit } => (TLOPEN + 1, Writer::new().qid(qid).u32(*iounit)),
+            Readdir { entries } => {
+                let len = entries.iter().map(DirEntry::encoded_len).sum::<usize>();
+                let mut w = Writer::new().u32(len as u32);
+                for entry in entries {
+                    w = w
+                        .qid(&entry.qid)
+                        .u64(entry.offset)
+                        .u8(entry.ty)
+                        .str(&entry.name);
+                }
+                (TREADDIR + 1, w)
+            }
+            Read { data } => (TREAD + 1, Writer::new().u32(data.len() as u32).bytes(data)),
+            Write { count } => (TWRITE + 1, Writer::new().u32(*count)),
+            Clunk => (TCLUNK + 1, Writer::new()),
+            Flush => (TFLUSH + 1, Writer::new()),
+            Statfs(statfs) => (
+                TSTATFS + 1,
+                Writer::new()
+                    .u32(statfs.ty)
+                    .u32(statfs.bsize)
+                    .u64(statfs.blocks)
+                    .u64(statfs.bfree)
+                    .u64(statfs.bavail)
+                    .u64(statfs.files)
+                    .u64(statfs.ffree)
+                    .u64(statfs.fsid)
+                    .u32(statfs.namelen),
+            ),
+            Fsync => (TFSYNC + 1, Writer::new()),
+        };
+
+        w.finish(ty, tag)
+    }
+
+    /// Decode a whole message, as returned by `read_message`.
+    pub fn decode(msg: &[u8]) -> io::Result<(u16, Self)> {
+        use Rmessage::*;
+
+        let (ty, tag, mut r) = Reader::header(msg)?;
+
+        let ret = match ty.wrapping_sub(1) {
+            ty if ty + 1 == RLERROR => Lerror { ecode: r.u32()? },
+            TVERSION => Version {
+                msize: r.u32()?,
+                version: r.str()?,
+            },
+            TATTACH => Attach { qid: r.qid()? },
+            TWALK => Walk {
+                qids: (0..r.u16()?).map(|_| r.qid()).collect::<io::Result<_>>()?,
+            },
+            TGETATTR => {
+                let mut attr = Attr {
+                    valid: r.u64()?,
+                    qid: r.qid()?,
+                    mode: r.u32()?,
+                    uid: r.u32()?,
+                    gid: r.u32()?,
+                    nlink: r.u64()?,
+                    rdev: r.u64()?,
+                    size: r.u64()?,
+                    blksize: r.u64()?,
+                    blocks: r.u64()?,
+                    ..Default::default()
+                };
+                for time in [
+                    &mut attr.atime,
+                    &mut attr.mtime,
+                    &mut attr.ctime,
+                    &mut attr.btime,
+                ] {
+                    time.sec = r.u64()?;
+                    time.nsec = r.u64()?;
+                }
+                attr.gen = r.u64()?;
+                attr.data_version = r.u64()?;
+                Getattr(attr)
+            }
+            TLOPEN => Lopen {
+                qid: r.qid()?,
+                iounit: r.u32()?,
+            },
+            TREADDIR => {
+                let len = r.u32()? as usize;
+                let mut r = Reader(r.bytes(len)?);
+                let mut entries = vec![];
+                while !r.0.is_empty() {
+                    entries.push(DirEntry {
+                        qid: r.qid()?,
+                        offset: r.u64()?,
+                        ty: r.u8()?,
+                        name: r.str()?,
+                    });
+                }
+                Readdir { entries }
+            }
+            TREAD => {
+                let len = r.u32()? as usize;
+                Read {
+                    data: r.bytes(len)?.to_vec(),
+                }
+            }
+            TWRITE => Write { count: r.u32()? },
+            TCLUNK => Clunk,
+            TFLUSH => Flush,
+            TSTATFS => Statfs(self::Statfs {
+                ty: r.u32()?,
+                bsize: r.u32()?,
+                blocks: r.u64()?,
+                bfree: r.u64()?,
+                bavail: r.u64()?,
+                files: r.u64()?,
+                ffree: r.u64()?,
+                fsid: r.u64()?,
+                namelen: r.u32()?,
+            }),
+            TFSYNC => Fsync,
+            _ => return Err(io::ErrorKind::InvalidData.into()),
+        };
+
+        Ok((tag, ret))
+    }
+}
+
+/// Read a single whole message, which may be no longer than `msize`.
+pub async fn read_message(
+    reader: &mut (impl AsyncRead + Unpin),
+    msize: u32,
+) -> io::Result<Vec<u8>> {
+    let size = reader.read_u32_le().await?;
+
+    if size > msize || (size as usize) < HEADER_LEN {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    let mut msg = size.to_le_bytes().to_vec();
+    msg.resize(size as usize, 0);
+    reader.read_exact(&mut msg[4..]).await?;
+
+    Ok(msg)
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn new() -> Self {
+        // Room for the header
+        Self(vec![0; HEADER_LEN])
+    }
+
+    fn u8(mut self, v: u8) -> Self {
+        self.0.push(v);
+        self
+    }
+
+    fn u16(self, v: u16) -> Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn u32(self, v: u32) -> Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn u64(self, v: u64) -> Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn bytes(mut self, v: &[u8]) -> Self {
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn str(self, v: &str) -> Self {
+        self.u16(v.len() as u16).bytes(v.as_bytes())
+    }
+
+    fn qid(self, qid: &Qid) -> Self {
+        self.u8(qid.ty).u32(qid.version).u64(qid.path)
+    }
+
+    fn finish(mut self, ty: u8, tag: u16) -> Vec<u8> {
+        let size = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&size.to_le_bytes());
+        self.0[4] = ty;
+        self.0[5..7].copy_from_slice(&tag.to_le_bytes());
+        self.0
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Split a message into its type, tag and body.
+    fn header(msg: &'a [u8]) -> io::Result<(u8, u16, Self)> {
+        let mut r = Self(msg);
+        r.u32()?;
+        Ok((r.u8()?, r.u16()?, r))
+    }
+
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let (ret, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(ret)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| io::ErrorKind::InvalidData.into())
+    }
+
+    fn qid(&mut self) -> io::Result<Qid> {
+        Ok(Qid {
+            ty: self.u8()?,
+            version: self.u32()?,
+            path: self.u64()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn roundtrip() {
+        let qid = Qid {
+            ty: QTDIR,
+            version: 1,
+            path: 0x1234,
+        };
+
+        let requests = [
+            Tmessage::Version {
+                msize: 8192,
+                version: VERSION.into(),
+            },
+            Tmessage::Walk {
+                fid: 1,
+                newfid: 2,
+                wnames: vec!["os".into(), "native".into()],
+            },
+            Tmessage::Write {
+                fid: 2,
+                offset: 0x1000,
+                data: b"hello".to_vec(),
+            },
+            Tmessage::Other(26),
+        ];
+
+        for (tag, msg) in requests.into_iter().enumerate() {
+            let encoded = msg.encode(tag as u16);
+            assert_eq!(Tmessage::decode(&encoded).unwrap(), (tag as u16, msg));
+        }
+
+        let responses = [
+            Rmessage::Lerror { ecode: 2 },
+            Rmessage::Walk {
+                qids: vec![qid, qid],
+            },
+            Rmessage::Getattr(Attr {
+                qid,
+                size: 42,
+                mtime: Time { sec: 1, nsec: 2 },
+                data_version: 3,
+                ..Default::default()
+            }),
+            Rmessage::Readdir {
+                entries: vec![DirEntry {
+                    qid,
+                    offset: 1,
+                    ty: DT_DIR,
+                    name: "connector".into(),
+                }],
+            },
+            Rmessage::Clunk,
+        ];
+
+        for (tag, msg) in responses.into_iter().enumerate() {
+            let encoded = msg.encode(tag as u16);
+            assert_eq!(Rmessage::decode(&encoded).unwrap(), (tag as u16, msg));
+        }
+
+        // Truncated messages are rejected
+        let encoded = Tmessage::Clunk { fid: 1 }.encode(0);
+        assert!(Tmessage::decode(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
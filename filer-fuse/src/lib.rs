@@ -2,9 +2,13 @@ use filer::prelude::v1::*;
 
 use fuse_mt::*;
 use log::*;
+use std::collections::HashSet;
 use std::ffi::{OsStr, OsString};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
 
 use time::*;
 
@@ -14,6 +18,8 @@ pub struct FilerFs {
     uid: u32,
     gid: u32,
     readonly: bool,
+    /// Handles opened on the node, which have not been released yet.
+    handles: Mutex<HashSet<usize>>,
 }
 
 const TTL: Timespec = Timespec { sec: 1, nsec: 0 };
@@ -30,6 +36,23 @@ fn path_to_str(path: &Path) -> String {
     ret.strip_prefix('/').unwrap_or(&ret).to_string()
 }
 
+/// Error number of a failed read or write, which is EIO unless the node gave a reason.
+fn io_errno(err: &std::io::Error) -> libc::c_int {
+    match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+        Some(Error(_, kind)) => match kind {
+            ErrorKind::NotFound | ErrorKind::InvalidPath | ErrorKind::PluginNotFound => {
+                libc::ENOENT
+            }
+            ErrorKind::AlreadyExists => libc::EEXIST,
+            ErrorKind::ReadOnly => libc::EROFS,
+            ErrorKind::NotSupported | ErrorKind::NotImplemented => libc::EOPNOTSUPP,
+            ErrorKind::InvalidArgument => libc::EINVAL,
+            _ => libc::EIO,
+        },
+        None => libc::EIO,
+    }
+}
+
 impl FilesystemMT for FilerFs {
     /// Called on mount, before any other function.
     fn init(&self, _req: RequestInfo) -> ResultEmpty {
@@ -295,7 +318,10 @@ impl FilesystemMT for FilerFs {
             // TODO: handle readonly flags, directories
             // let masked_flags =
             //    flags & !libc::O_WRONLY as u32 & !libc::O_RDWR as u32;
-            Ok(handle) => Ok((handle as u64, flags | FOPEN_DIRECT_IO)),
+            Ok(handle) => {
+                self.handles.lock().unwrap().insert(handle);
+                Ok((handle as u64, flags | FOPEN_DIRECT_IO))
+            }
             Err(_) => Err(libc::ENOENT),
         }
     }
@@ -371,7 +397,7 @@ impl FilesystemMT for FilerFs {
                 Ok(written) => Ok(written as u32),
                 Err(e) => {
                     error!("{e}");
-                    Err(libc::EIO)
+                    Err(io_errno(&e))
                 }
             },
             _ => Err(libc::EIO),
@@ -414,6 +440,7 @@ impl FilesystemMT for FilerFs {
         _lock_owner: u64,
         _flush: bool,
     ) -> ResultEmpty {
+        self.handles.lock().unwrap().remove(&(fh as usize));
         self.node.close(fh as _).map_err(|_| libc::EIO)
     }
 
@@ -621,10 +648,92 @@ impl FilesystemMT for FilerFs {
     }
 }
 
-/// Drops the filesystem and removes it from the global state.
+/// Closes the handles left open once the filesystem goes away.
 impl Drop for FilerFs {
     fn drop(&mut self) {
-        // TODO: drop all opened handles
+        for handle in self.handles.get_mut().unwrap().drain() {
+            if let Err(e) = self.node.close(handle) {
+                warn!("unable to close handle {handle}: {e}");
+            }
+        }
+    }
+}
+
+/// Filesystem mounted by [`mount`], which gets unmounted once dropped.
+#[must_use = "the filesystem is unmounted once dropped"]
+pub struct FuseMount {
+    mount_point: String,
+    session: Option<JoinHandle<()>>,
+}
+
+impl FuseMount {
+    /// Unmount the filesystem, and wait until all of its handles are closed.
+    ///
+    /// On Linux, a filesystem still in use is lazily unmounted instead. It disappears from the
+    /// mount point right away, but keeps being served until its last handle is closed, which is
+    /// not waited for.
+    pub fn unmount(mut self) -> io::Result<()> {
+        self.unmount_session()
+    }
+
+    fn unmount_session(&mut self) -> io::Result<()> {
+        let session = match self.session.take() {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+
+        // Mounting failed, or the filesystem got unmounted externally
+        if session.is_finished() {
+            return join(session);
+        }
+
+        info!("unmounting filesystem at {}", self.mount_point);
+
+        #[cfg(target_os = "linux")]
+        let status = Command::new("fusermount")
+            .arg("-u")
+            .arg(&self.mount_point)
+            .status()?;
+        #[cfg(not(target_os = "linux"))]
+        let status = Command::new("umount").arg(&self.mount_point).status()?;
+
+        if status.success() {
+            // The session ends once the kernel lets go of the filesystem
+            return join(session);
+        }
+
+        #[cfg(target_os = "linux")]
+        if Command::new("fusermount")
+            .arg("-uz")
+            .arg(&self.mount_point)
+            .status()?
+            .success()
+        {
+            warn!(
+                "{} is still in use, it is served until its last handle is closed",
+                self.mount_point
+            );
+            return Ok(());
+        }
+
+        Err(io::Error::other(format!(
+            "unable to unmount {}: {status}",
+            self.mount_point
+        )))
+    }
+}
+
+fn join(session: JoinHandle<()>) -> io::Result<()> {
+    session
+        .join()
+        .map_err(|_| io::Error::other("the FUSE session panicked"))
+}
+
+impl Drop for FuseMount {
+    fn drop(&mut self) {
+        if let Err(e) = self.unmount_session() {
+            error!("unable to unmount {}: {e}", self.mount_point);
+        }
     }
 }
 
@@ -634,7 +743,7 @@ pub fn mount(
     allow_other: bool,
     uid: u32,
     gid: u32,
-) -> Result<()> {
+) -> Result<FuseMount> {
     if mount_point.is_empty() {
         return Err(ErrorKind::InvalidPath.into());
     }
@@ -654,22 +763,31 @@ pub fn mount(
         opts += &format!(",uid={uid},gid={gid}");
     }
 
-    std::thread::spawn(move || {
-        let opts = ["-o", &opts];
-        let mntopts = opts.iter().map(|o| o.as_ref()).collect::<Vec<&OsStr>>();
+    let session = {
+        let mount_point = mount_point.clone();
 
-        // the filesystem will add itself into the global scope
-        let vmfs = FilerFs {
-            node,
-            mount_point: mount_point.clone(),
-            uid,
-            gid,
-            readonly: false,
-        };
+        std::thread::spawn(move || {
+            let opts = ["-o", &opts];
+            let mntopts = opts.iter().map(|o| o.as_ref()).collect::<Vec<&OsStr>>();
 
-        // blocks until the fs is umounted
-        fuse_mt::mount(fuse_mt::FuseMT::new(vmfs, 8), &mount_point, &mntopts).unwrap();
-    });
+            let vmfs = FilerFs {
+                node,
+                mount_point: mount_point.clone(),
+                uid,
+                gid,
+                readonly: false,
+                handles: Default::default(),
+            };
+
+            // blocks until the fs is umounted
+            if let Err(e) = fuse_mt::mount(fuse_mt::FuseMT::new(vmfs, 8), &mount_point, &mntopts) {
+                error!("unable to mount filesystem at {mount_point}: {e}");
+            }
+        })
+    };
 
-    Ok(())
+    Ok(FuseMount {
+        mount_point,
+        session: Some(session),
+    })
 }
@@ -0,0 +1,700 @@
+//! HTTP gateway to a filer `Node`.
+//!
+//! The path of every request is the path within the node:
+//!
+//! * `GET` on a branch lists it as a JSON array of `{"name": ..., "is_branch": ...}` objects.
+//! * `GET` on a leaf streams its contents. A single byte `Range` may be requested.
+//! * `PUT` and `POST` write the request body to a leaf, at the `offset` query parameter.
+//! * `?metadata` returns the metadata of any path as a JSON object.
+//!
+//! Failed requests are answered with a matching status code, and a JSON object containing the
+//! `error` message.
+//!
+//! The daemon of `cloudflow-node` runs an `HttpServer` on its `http_addr`, if one is configured.
+//! Clients such as `cloudflow-cli` speak the protocol of `filer-tokio` instead, and can not connect
+//! to it.
+
+use filer::prelude::v1::*;
+
+use cglue::tuple::CTup2;
+
+use hyper::body::HttpBody;
+use hyper::header;
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+
+use log::*;
+use serde::Serialize;
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::Read;
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+
+/// Leaves are streamed in chunks of this size.
+const CHUNK_LEN: usize = 1 << 16;
+
+/// Upper bound for the body of a single write request.
+pub const MAX_WRITE_LEN: usize = 1 << 26;
+
+/// Serves a `Node` over HTTP.
+///
+/// There is no authentication of any kind, so only listen on trusted interfaces, and consider
+/// making the server `read_only`.
+pub struct HttpServer {
+    listener: TcpListener,
+    gateway: Gateway,
+}
+
+impl HttpServer {
+    pub fn new(listener: TcpListener, node: CArcSome<Node>) -> Self {
+        Self {
+            listener,
+            gateway: Gateway {
+                node,
+                read_only: false,
+            },
+        }
+    }
+
+    /// Reject all write requests.
+    pub fn read_only(mut self) -> Self {
+        self.gateway.read_only = true;
+        self
+    }
+
+    pub async fn run(self) -> hyper::Result<()> {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Serve requests until `shutdown` completes, and the requests in progress are finished.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> hyper::Result<()> {
+        let incoming = AddrIncoming::from_listener(self.listener)?;
+        let gateway = Arc::new(self.gateway);
+
+        let make_service = make_service_fn(move |_| {
+            let gateway = gateway.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let gateway = gateway.clone();
+                    async move { Ok::<_, Infallible>(gateway.handle(req).await) }
+                }))
+            }
+        });
+
+        Server::builder(incoming)
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown)
+            .await
+    }
+}
+
+/// Error response.
+struct Failure(StatusCode, String);
+
+impl Failure {
+    fn bad_request(msg: impl Into<String>) -> Self {
+        Self(StatusCode::BAD_REQUEST, msg.into())
+    }
+
+    fn into_response(self) -> Response<Body> {
+        #[derive(Serialize)]
+        struct ErrorBody {
+            error: String,
+        }
+
+        json(self.0, &ErrorBody { error: self.1 })
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        let status = match err.1 {
+            ErrorKind::NotFound | ErrorKind::InvalidPath | ErrorKind::PluginNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            ErrorKind::ReadOnly => StatusCode::FORBIDDEN,
+            ErrorKind::InvalidArgument | ErrorKind::Offset | ErrorKind::OutOfBounds => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+            ErrorKind::NotSupported | ErrorKind::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        Self(status, err.to_string())
+    }
+}
+
+type HttpResult<T> = std::result::Result<T, Failure>;
+
+#[derive(Serialize)]
+struct Entry {
+    name: String,
+    is_branch: bool,
+}
+
+#[derive(Serialize)]
+struct Metadata {
+    is_branch: bool,
+    has_read: bool,
+    has_write: bool,
+    has_rpc: bool,
+    size: Size,
+}
+
+impl From<NodeMetadata> for Metadata {
+    fn from(metadata: NodeMetadata) -> Self {
+        Self {
+            is_branch: metadata.is_branch,
+            has_read: metadata.has_read,
+            has_write: metadata.has_write,
+            has_rpc: metadata.has_rpc,
+            size: metadata.size,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Written {
+    written: usize,
+}
+
+/// Parameters passed in the query string.
+#[derive(Default)]
+struct Query {
+    metadata: bool,
+    offset: Size,
+}
+
+impl Query {
+    fn parse(query: &str) -> HttpResult<Self> {
+        let mut ret = Self::default();
+
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "metadata" => ret.metadata = true,
+                "offset" => {
+                    ret.offset = parse_size(value)
+                        .ok_or_else(|| Failure::bad_request(format!("invalid offset {value}")))?
+                }
+                _ => return Err(Failure::bad_request(format!("unknown parameter {key}"))),
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+struct Gateway {
+    node: CArcSome<Node>,
+    read_only: bool,
+}
+
+impl Gateway {
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        debug!("{} {}", req.method(), req.uri());
+
+        self.dispatch(req)
+            .await
+            .unwrap_or_else(Failure::into_response)
+    }
+
+    async fn dispatch(&self, req: Request<Body>) -> HttpResult<Response<Body>> {
+        let path =
+            decode_path(req.uri().path()).ok_or_else(|| Failure::bad_request("invalid path"))?;
+        let query = Query::parse(req.uri().query().unwrap_or(""))?;
+
+        match *req.method() {
+            Method::GET | Method::HEAD if query.metadata => {
+                let metadata = self.blocking(move |node| node.metadata(&path)).await?;
+                Ok(json(StatusCode::OK, &Metadata::from(metadata)))
+            }
+            Method::GET | Method::HEAD => self.get(path, req).await,
+            Method::PUT | Method::POST => self.put(path, query.offset, req).await,
+            _ => Err(Failure(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method not allowed".into(),
+            )),
+        }
+    }
+
+    /// Run a node operation on the blocking thread pool.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        func: impl FnOnce(&CArcSome<Node>) -> Result<T> + Send + 'static,
+    ) -> HttpResult<T> {
+        let node = self.node.clone();
+
+        tokio::task::spawn_blocking(move || func(&node))
+            .await
+            .map_err(|e| Failure(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(Into::into)
+    }
+
+    async fn get(&self, path: String, req: Request<Body>) -> HttpResult<Response<Body>> {
+        let (metadata, path) = self
+            .blocking(move |node| Ok((node.metadata(&path)?, path)))
+            .await?;
+
+        if metadata.is_branch {
+            let entries = self.blocking(move |node| list(node, &path)).await?;
+            return Ok(json(StatusCode::OK, &entries));
+        }
+
+        // Leaves of unknown size report a size of 0
+        let size = Some(metadata.size).filter(|&size| size > 0);
+
+        let range = match req.headers().get(header::RANGE) {
+            Some(range) => parse_range(range.to_str().unwrap_or(""), size)?,
+            None => None,
+        };
+
+        let (start, end) = match range {
+            Some((start, end)) => (start, Some(end)),
+            None => (0, size),
+        };
+
+        let mut response = Response::builder()
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header(header::CONTENT_TYPE, "application/octet-stream");
+
+        if let Some(end) = end {
+            response = response.header(header::CONTENT_LENGTH, end - start);
+        }
+
+        if range.is_some() {
+            let total = size.map_or("*".into(), |size| size.to_string());
+            let content_range = format!("bytes {}-{}/{}", start, end.unwrap() - 1, total);
+            response = response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, content_range);
+        }
+
+        let body = if req.method() == Method::HEAD {
+            Body::empty()
+        } else {
+            let handle = self.blocking(move |node| node.open(&path)).await?;
+            stream(self.node.clone(), handle, start, end)
+        };
+
+        Ok(response.body(body).unwrap())
+    }
+
+    async fn put(
+        &self,
+        path: String,
+        offset: Size,
+        req: Request<Body>,
+    ) -> HttpResult<Response<Body>> {
+        if self.read_only {
+            return Err(Error(ErrorOrigin::Node, ErrorKind::ReadOnly).into());
+        }
+
+        let data = read_body(req.into_body()).await?;
+
+        let written = self
+            .blocking(move |node| {
+                let metadata = node.metadata(&path)?;
+
+                if metadata.is_branch || !metadata.has_write {
+                    return Err(Error(ErrorOrigin::Node, ErrorKind::ReadOnly));
+                }
+
+                let handle = node.open_handle(&path)?;
+                write_at(&handle, offset, &data)
+            })
+            .await?;
+
+        Ok(json(StatusCode::OK, &Written { written }))
+    }
+}
+
+fn json(status: StatusCode, value: &impl Serialize) -> Response<Body> {
+    // None of the values sent can fail to serialize
+    let body = serde_json::to_vec(value).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .unwrap()
+}
+
+fn list(node: &CArcSome<Node>, path: &str) -> Result<Vec<Entry>> {
+    let mut entries = vec![];
+
+    let cb = &mut |entry: ListEntry| {
+        entries.push(Entry {
+            name: String::from(&*entry.name),
+            is_branch: entry.is_branch,
+        });
+        true
+    };
+
+    node.list(path, &mut cb.into())?;
+
+    Ok(entries)
+}
+
+/// Stream the contents of an open leaf from `start` up to `end`, or until it can not be read any
+/// further. The handle gets closed afterwards.
+fn stream(node: CArcSome<Node>, handle: usize, start: Size, end: Option<Size>) -> Body {
+    let (mut sender, body) = Body::channel();
+    let runtime = tokio::runtime::Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        let mut cursor = ObjCursor::from((&node, handle, start));
+        let mut pos = start;
+
+        while end.is_none_or(|end| pos < end) {
+            let len = end.map_or(CHUNK_LEN, |end| {
+                core::cmp::min(CHUNK_LEN as Size, end - pos) as usize
+            });
+            let mut buf = vec![0; len];
+
+            let read = match cursor.read(&mut buf) {
+                Ok(read) if read > 0 => read,
+                _ => break,
+            };
+
+            buf.truncate(read);
+
+            if runtime.block_on(sender.send_data(buf.into())).is_err() {
+                // The client went away
+                return;
+            }
+
+            pos += read as Size;
+        }
+
+        // Make sure the client does not mistake the response for a complete one
+        if end.is_some_and(|end| pos < end) {
+            sender.abort();
+        }
+    });
+
+    body
+}
+
+/// Write `data` at `offset`, and return the amount of bytes written before the first failure.
+fn write_at(handle: &ObjHandle<CArcSome<Node>>, offset: Size, data: &[u8]) -> Result<usize> {
+    let mut failed: Option<(Size, Error)> = None;
+
+    {
+        let out_fail = &mut |fail: ROFailData| {
+            let (CTup2(addr, _), err) = fail.into();
+            if failed.is_none_or(|(first, _)| addr < first) {
+                failed = Some((addr, err));
+            }
+            true
+        };
+        let mut out_fail = out_fail.into();
+
+        let inp = &mut core::iter::once(CTup2(offset, data.into()));
+
+        handle.write(VecOps {
+            inp: inp.into(),
+            out: None,
+            out_fail: Some(&mut out_fail),
+        })?;
+    }
+
+    match failed {
+        Some((addr, _)) if addr > offset => Ok((addr - offset) as usize),
+        Some((_, err)) => Err(err),
+        None => Ok(data.len()),
+    }
+}
+
+async fn read_body(mut body: Body) -> HttpResult<Vec<u8>> {
+    let mut data = vec![];
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| Failure::bad_request(e.to_string()))?;
+
+        if data.len() + chunk.len() > MAX_WRITE_LEN {
+            return Err(Failure(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("writes are limited to {MAX_WRITE_LEN} bytes"),
+            ));
+        }
+
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data)
+}
+
+/// Turn the percent-encoded path of a URL into a node path.
+fn decode_path(path: &str) -> Option<String> {
+    let mut out = vec![];
+    let mut bytes = path.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            out.push(u8::from_str_radix(core::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            out.push(b);
+        }
+    }
+
+    String::from_utf8(out)
+        .ok()
+        .map(|path| path.trim_matches('/').to_string())
+}
+
+/// Parse a decimal, or `0x` prefixed hexadecimal number.
+fn parse_size(value: &str) -> Option<Size> {
+    match value.strip_prefix("0x") {
+        Some(hex) => Size::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Parse the value of a `Range` header into a `[start, end)` range.
+///
+/// Returns `None` if the header is to be ignored, which is the case for anything but a single
+/// byte range. Ranges reaching to the end of a leaf require its size to be known.
+fn parse_range(range: &str, size: Option<Size>) -> HttpResult<Option<(Size, Size)>> {
+    let unsatisfiable = || {
+        Failure(
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            format!("unsatisfiable range {range}"),
+        )
+    };
+
+    let spec = match range.strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+
+    let (start, end) = match (first, last) {
+        // Suffix of the given length
+        ("", last) => {
+            let len = match last.parse::<Size>() {
+                Ok(len) => len,
+                Err(_) => return Ok(None),
+            };
+            let size = size.ok_or_else(unsatisfiable)?;
+            (size.saturating_sub(len), size)
+        }
+        (first, "") => match first.parse::<Size>() {
+            Ok(start) => (start, size.ok_or_else(unsatisfiable)?),
+            Err(_) => return Ok(None),
+        },
+        (first, last) => match (first.parse::<Size>(), last.parse::<Size>()) {
+            (Ok(start), Ok(last)) if start <= last => {
+                let end = last.saturating_add(1);
+                (start, size.map_or(end, |size| core::cmp::min(end, size)))
+            }
+            _ => return Ok(None),
+        },
+    };
+
+    if start >= end {
+        return Err(unsatisfiable());
+    }
+
+    Ok(Some((start, end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{Client, HeaderMap};
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+    use tokio::runtime::Runtime;
+
+    /// In-memory backend with a single readable and writeable leaf.
+    #[derive(Default)]
+    struct BufBackend {
+        data: Mutex<Vec<u8>>,
+    }
+
+    impl Backend for BufBackend {
+        fn read(&self, _: BackendStack, _: usize, mut data: VecOps<RWData>) -> Result<()> {
+            let buf = self.data.lock().unwrap();
+            for CTup2(off, mut to) in data.inp {
+                let (off, len) = (off as usize, to.len());
+                if off + len <= buf.len() {
+                    to.copy_from_slice(&buf[off..(off + len)]);
+                    opt_call(data.out.as_deref_mut(), CTup2(off as Size, to));
+                } else {
+                    let e = Error(ErrorOrigin::Read, ErrorKind::OutOfBounds);
+                    opt_call(
+                        data.out_fail.as_deref_mut(),
+                        (CTup2(off as Size, to), e).into(),
+                    );
+                }
+            }
+            Ok(())
+        }
+
+        fn write(&self, _: BackendStack, _: usize, mut data: VecOps<ROData>) -> Result<()> {
+            let mut buf = self.data.lock().unwrap();
+            for CTup2(off, from) in data.inp {
+                let off = off as usize;
+                if buf.len() < off + from.len() {
+                    buf.resize(off + from.len(), 0);
+                }
+                buf[off..(off + from.len())].copy_from_slice(&from);
+                opt_call(data.out.as_deref_mut(), CTup2(off as Size, from));
+            }
+            Ok(())
+        }
+
+        fn rpc(&self, _: BackendStack, _: usize, _: &[u8], _: &mut [u8]) -> Result<()> {
+            Err(Error(ErrorOrigin::Backend, ErrorKind::NotSupported))
+        }
+
+        fn close(&self, _: BackendStack, _: usize) -> Result<()> {
+            Ok(())
+        }
+
+        fn open(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<usize> {
+            if path == "buf" {
+                Ok(0)
+            } else {
+                Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound))
+            }
+        }
+
+        fn metadata(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<NodeMetadata> {
+            match path {
+                "" => Ok(NodeMetadata::branch()),
+                "buf" => Ok(NodeMetadata {
+                    has_read: true,
+                    has_write: true,
+                    size: self.data.lock().unwrap().len() as Size,
+                    ..Default::default()
+                }),
+                _ => Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+            }
+        }
+
+        fn list(
+            &self,
+            _: BackendStack,
+            _: &str,
+            _: &CPluginStore,
+            out: &mut OpaqueCallback<ListEntry>,
+        ) -> Result<()> {
+            let _ = out.call(ListEntry::new("buf".into(), false));
+            Ok(())
+        }
+    }
+
+    fn serve(read_only: bool) -> (Runtime, SocketAddr) {
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut server = HttpServer::new(listener, Node::new(BufBackend::default()).into());
+        if read_only {
+            server = server.read_only();
+        }
+        runtime.spawn(server.run());
+
+        (runtime, addr)
+    }
+
+    fn request(
+        runtime: &Runtime,
+        req: hyper::http::request::Builder,
+        body: &[u8],
+    ) -> (StatusCode, HeaderMap, Vec<u8>) {
+        runtime.block_on(async {
+            let req = req.body(Body::from(body.to_vec())).unwrap();
+            let response = Client::new().request(req).await.unwrap();
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (status, headers, body.to_vec())
+        })
+    }
+
+    fn get(runtime: &Runtime, addr: SocketAddr, path: &str) -> (StatusCode, Vec<u8>) {
+        let req = Request::get(format!("http://{addr}{path}"));
+        let (status, _, body) = request(runtime, req, &[]);
+        (status, body)
+    }
+
+    #[test]
+    pub fn http_list_metadata() {
+        let (runtime, addr) = serve(false);
+
+        let (status, body) = get(&runtime, addr, "/");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, br#"[{"name":"buf","is_branch":false}]"#);
+
+        let (status, body) = get(&runtime, addr, "/buf?metadata");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            br#"{"is_branch":false,"has_read":true,"has_write":true,"has_rpc":false,"size":0}"#
+        );
+    }
+
+    #[test]
+    pub fn http_read_write() {
+        let (runtime, addr) = serve(false);
+
+        let req = Request::put(format!("http://{addr}/buf?offset=0x4"));
+        let (status, _, body) = request(&runtime, req, b"hello");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, br#"{"written":5}"#);
+
+        let (status, body) = get(&runtime, addr, "/buf");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"\0\0\0\0hello");
+
+        let ranges = [
+            ("bytes=4-6", "bytes 4-6/9", &b"hel"[..]),
+            ("bytes=-2", "bytes 7-8/9", b"lo"),
+        ];
+
+        for (range, content_range, expected) in ranges {
+            let req = Request::get(format!("http://{addr}/buf")).header(header::RANGE, range);
+            let (status, headers, body) = request(&runtime, req, &[]);
+            assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+            assert_eq!(headers[header::CONTENT_RANGE], content_range);
+            assert_eq!(body, expected);
+        }
+
+        let req = Request::get(format!("http://{addr}/buf")).header(header::RANGE, "bytes=9-");
+        let (status, _, _) = request(&runtime, req, &[]);
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+
+    #[test]
+    pub fn http_errors() {
+        let (runtime, addr) = serve(true);
+
+        let (status, body) = get(&runtime, addr, "/missing");
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, br#"{"error":"backend: not found"}"#);
+
+        let (status, _) = get(&runtime, addr, "/buf?bogus");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let req = Request::put(format!("http://{addr}/buf"));
+        let (status, _, _) = request(&runtime, req, b"hello");
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let req = Request::delete(format!("http://{addr}/buf"));
+        let (status, _, _) = request(&runtime, req, &[]);
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
@@ -0,0 +1,159 @@
+//! Authentication of clients connecting to a `FilerServer`.
+//!
+//! Right after the protocol handshake, clients present their credentials, and the server's
+//! `Authenticator` either turns them into an `Identity`, or drops the connection.
+
+use std::io;
+use std::path::Path;
+
+/// Who a connected client is, and what it may do.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    /// Client may not perform write operations or remote procedure calls.
+    pub read_only: bool,
+}
+
+impl Identity {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            read_only: false,
+        }
+    }
+
+    pub fn read_only(self) -> Self {
+        Self {
+            read_only: true,
+            ..self
+        }
+    }
+}
+
+/// Verifies credentials presented by connecting clients.
+pub trait Authenticator: Send + Sync {
+    /// Returns the identity of the client, or `None` if it is to be rejected.
+    fn authenticate(&self, credentials: &[u8]) -> Option<Identity>;
+}
+
+/// Accepts every client with full access.
+pub struct NoAuth;
+
+impl Authenticator for NoAuth {
+    fn authenticate(&self, _: &[u8]) -> Option<Identity> {
+        Some(Identity::new("anonymous"))
+    }
+}
+
+/// Accepts clients presenting one of the pre-shared tokens.
+#[derive(Default)]
+pub struct TokenAuth {
+    tokens: Vec<(Vec<u8>, Identity)>,
+}
+
+impl TokenAuth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_token(mut self, token: impl Into<Vec<u8>>, identity: Identity) -> Self {
+        self.tokens.push((token.into(), identity));
+        self
+    }
+
+    /// Parse a key file.
+    ///
+    /// Every line of the file has the form `<token> [name] [ro]`. Lines starting with `#` are
+    /// ignored. Clients without a name are called `client`, and `ro` makes the client read-only.
+    pub fn from_key_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn parse(keys: &str) -> io::Result<Self> {
+        let mut ret = Self::new();
+
+        for line in key_lines(keys) {
+            let mut words = line.split_whitespace();
+            // key_lines never yields empty lines
+            let token = words.next().unwrap();
+            let mut identity = Identity::new(words.next().unwrap_or("client"));
+
+            match words.next() {
+                Some("ro") => identity = identity.read_only(),
+                Some(_) => return Err(io::ErrorKind::InvalidData.into()),
+                None => {}
+            }
+
+            ret = ret.with_token(token, identity);
+        }
+
+        if ret.tokens.is_empty() {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        Ok(ret)
+    }
+}
+
+impl Authenticator for TokenAuth {
+    fn authenticate(&self, credentials: &[u8]) -> Option<Identity> {
+        // Every token is checked, so that the timing does not reveal which one matched
+        self.tokens
+            .iter()
+            .fold(None, |found, (token, identity)| {
+                if constant_time_eq(token, credentials) {
+                    Some(identity)
+                } else {
+                    found
+                }
+            })
+            .cloned()
+    }
+}
+
+/// Read the token a client should present from a key file.
+///
+/// This is the first token in the file, so a key file containing a single token can be shared by
+/// the server and its clients.
+pub fn read_token_file(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let keys = std::fs::read_to_string(path)?;
+
+    let token = key_lines(&keys)
+        .next()
+        .and_then(|line| line.split_whitespace().next())
+        .map(|token| token.as_bytes().to_vec());
+
+    token.ok_or_else(|| io::ErrorKind::InvalidData.into())
+}
+
+fn key_lines(keys: &str) -> impl Iterator<Item = &str> {
+    keys.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parse_key_file() {
+        let auth = TokenAuth::parse("# keys\nsecret admin\n\nguesttoken guest ro\nanon\n").unwrap();
+
+        assert_eq!(auth.authenticate(b"secret"), Some(Identity::new("admin")));
+        assert_eq!(
+            auth.authenticate(b"guesttoken"),
+            Some(Identity::new("guest").read_only())
+        );
+        assert_eq!(auth.authenticate(b"anon"), Some(Identity::new("client")));
+        assert_eq!(auth.authenticate(b"secre"), None);
+        assert_eq!(auth.authenticate(b""), None);
+
+        assert!(TokenAuth::parse("# nothing\n").is_err());
+        assert!(TokenAuth::parse("secret admin rw\n").is_err());
+    }
+}
@@ -0,0 +1,817 @@
+use crate::*;
+
+use cglue::callback::OpaqueCallback;
+use cglue::result::IntError;
+use cglue::tuple::CTup2;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs};
+use tokio::runtime::{Builder, Runtime};
+
+/// Synchronous `Frontend` backed by a remote `FilerServer`.
+///
+/// Every request is tagged with an ID, so any number of threads can have requests in flight on
+/// the same connection at once. Responses are dispatched by a background task.
+///
+/// Once the connection fails, the client is considered disconnected and every further request
+/// fails immediately, unless reconnecting has been enabled through `with_reconnect`.
+///
+/// Handles given out by the client are its own, and are mapped to the ones of the server. This
+/// keeps them valid across reconnects.
+pub struct FilerClient {
+    runtime: Runtime,
+    connection: RwLock<Arc<Connection>>,
+    /// Opens a new stream to the server, if the client knows how to.
+    connect: Option<Connect>,
+    credentials: Vec<u8>,
+    reconnect: Option<ReconnectPolicy>,
+    /// Held while reconnecting, so that only a single thread does so.
+    reconnecting: Mutex<()>,
+    handles: Mutex<HashMap<usize, OpenHandle>>,
+    next_handle: AtomicUsize,
+    next_id: AtomicU64,
+    timeout: Option<Duration>,
+}
+
+/// How a `FilerClient` reconnects to the server once the connection is lost.
+///
+/// Reconnecting happens on the next request after the loss. Requests in flight at the time of the
+/// loss fail, as the server may or may not have performed them. Once connected again, all open
+/// handles are re-opened by their path.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// Number of connection attempts before giving up, `None` to keep trying forever.
+    pub max_attempts: Option<usize>,
+    /// Delay after the first failed attempt.
+    pub initial_backoff: Duration,
+    /// The delay doubles with every failed attempt, up to this value.
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: Some(10),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Stream for T {}
+
+type Connect = Box<
+    dyn Fn() -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Stream>>> + Send>> + Send + Sync,
+>;
+
+/// A handle opened through the client.
+struct OpenHandle {
+    path: String,
+    /// Handle on the current connection, `None` if re-opening it failed.
+    remote: Option<usize>,
+}
+
+/// A single connection to the server.
+struct Connection {
+    /// Capabilities negotiated with the server.
+    hello: Hello,
+    /// Encoded request frames to be written out.
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+    shared: Arc<Shared>,
+}
+
+impl Connection {
+    /// Perform the handshake and authentication, and spawn the tasks driving the connection.
+    async fn establish(
+        mut stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        credentials: &[u8],
+    ) -> Result<Self> {
+        let local = Hello::default();
+        local.write_to(&mut stream).await?;
+        let hello = local.negotiate(&Hello::read_from(&mut stream).await?)?;
+        send_credentials(&mut stream, credentials).await?;
+
+        let (reader, writer) = tokio::io::split(stream);
+        let (frames, outgoing) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared::default());
+
+        tokio::spawn(read_frames(reader, shared.clone()));
+        tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                let _ = write_frames(writer, outgoing).await;
+                shared.disconnect();
+            }
+        });
+
+        Ok(Self {
+            hello,
+            frames,
+            shared,
+        })
+    }
+
+    fn is_disconnected(&self) -> bool {
+        self.shared.disconnected.load(Ordering::Relaxed)
+    }
+}
+
+/// State shared with the tasks driving the connection.
+#[derive(Default)]
+struct Shared {
+    /// Response channels of requests in flight.
+    pending: Mutex<HashMap<u64, mpsc::UnboundedSender<Frame>>>,
+    disconnected: AtomicBool,
+}
+
+impl Shared {
+    fn disconnect(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        self.disconnected.store(true, Ordering::Relaxed);
+        // Dropping the senders wakes up everyone waiting for a response
+        pending.clear();
+    }
+}
+
+/// Stops routing responses of a request once nobody waits for them.
+struct PendingGuard<'a>(&'a Shared, u64);
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.pending.lock().unwrap().remove(&self.1);
+    }
+}
+
+/// Route incoming response frames to their requests until the stream fails.
+async fn read_frames(reader: impl AsyncRead + Unpin, shared: Arc<Shared>) {
+    let mut reader = BufReader::new(reader);
+
+    while let Ok(frame) = Frame::read_from(&mut reader).await {
+        // Responses to cancelled requests are simply dropped
+        if let Some(sender) = shared.pending.lock().unwrap().get(&frame.id) {
+            let _ = sender.send(frame);
+        }
+    }
+
+    shared.disconnect();
+}
+
+fn disconnected() -> Error {
+    Error(ErrorOrigin::Io, ErrorKind::Uninitialized)
+}
+
+fn unsupported() -> Error {
+    Error(ErrorOrigin::Io, ErrorKind::NotSupported)
+}
+
+fn unknown_handle() -> Error {
+    Error(ErrorOrigin::Node, ErrorKind::NotFound)
+}
+
+impl FilerClient {
+    /// Create a client from an already connected stream.
+    ///
+    /// `runtime` is used to drive all I/O, and it must be the runtime `stream` was created in.
+    ///
+    /// This performs the protocol handshake, and fails with `VersionMismatch` if the server
+    /// speaks a different protocol version. Afterwards, `credentials` are presented to the
+    /// server's `Authenticator`. `InvalidArgument` is returned if they are rejected.
+    ///
+    /// Such a client does not know how to reach the server again, so it can not reconnect.
+    pub fn new<T: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        stream: T,
+        runtime: Runtime,
+        credentials: &[u8],
+    ) -> Result<Self> {
+        let connection = runtime.block_on(Connection::establish(stream, credentials))?;
+
+        Ok(Self {
+            runtime,
+            connection: RwLock::new(Arc::new(connection)),
+            connect: None,
+            credentials: credentials.to_vec(),
+            reconnect: None,
+            reconnecting: Mutex::new(()),
+            handles: Default::default(),
+            next_handle: AtomicUsize::new(0),
+            next_id: AtomicU64::new(0),
+            timeout: None,
+        })
+    }
+
+    /// Create a client connecting through `connect`, which is also used for reconnecting.
+    fn with_connect(runtime: Runtime, connect: Connect, credentials: &[u8]) -> Result<Self> {
+        let stream = runtime.block_on(connect())?;
+        Ok(Self {
+            connect: Some(connect),
+            ..Self::new(stream, runtime, credentials)?
+        })
+    }
+
+    /// Connect to a `FilerServer` listening on the given TCP address.
+    pub fn connect(addr: impl ToSocketAddrs, credentials: &[u8]) -> Result<Self> {
+        let runtime = Self::build_runtime()?;
+
+        // Resolve the address once, so that it can be reused for reconnecting.
+        let addrs = runtime.block_on(lookup_host(addr))?.collect::<Vec<_>>();
+
+        Self::with_connect(
+            runtime,
+            Box::new(move || {
+                let addrs = addrs.clone();
+                Box::pin(async move {
+                    let stream = TcpStream::connect(addrs.as_slice()).await?;
+                    stream.set_nodelay(true)?;
+                    Ok(Box::new(stream) as Box<dyn Stream>)
+                })
+            }),
+            credentials,
+        )
+    }
+
+    /// Connect to a `FilerServer` listening on the given unix domain socket.
+    #[cfg(unix)]
+    pub fn connect_unix(path: impl AsRef<Path>, credentials: &[u8]) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        Self::with_connect(
+            Self::build_runtime()?,
+            Box::new(move || {
+                let path = path.clone();
+                Box::pin(async move {
+                    let stream = UnixStream::connect(path).await?;
+                    Ok(Box::new(stream) as Box<dyn Stream>)
+                })
+            }),
+            credentials,
+        )
+    }
+
+    /// Connect to a `FilerServer` running on a `DuplexListener` in the same process.
+    pub fn connect_duplex(connector: &DuplexConnector, credentials: &[u8]) -> Result<Self> {
+        let connector = connector.clone();
+
+        Self::with_connect(
+            Self::build_runtime()?,
+            Box::new(move || {
+                let stream = connector.connect();
+                Box::pin(async move { Ok(Box::new(stream?) as Box<dyn Stream>) })
+            }),
+            credentials,
+        )
+    }
+
+    /// Runtime with a single worker, so that responses get dispatched even while no request is
+    /// being waited upon.
+    fn build_runtime() -> io::Result<Runtime> {
+        Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+    }
+
+    /// Capabilities negotiated with the server.
+    pub fn hello(&self) -> Hello {
+        self.connection.read().unwrap().hello
+    }
+
+    /// Fail requests that do not complete within the given duration.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Reconnect to the server according to `policy` once the connection is lost.
+    ///
+    /// This has no effect on clients created through `new`.
+    pub fn with_reconnect(self, policy: ReconnectPolicy) -> Self {
+        Self {
+            reconnect: Some(policy),
+            ..self
+        }
+    }
+
+    /// Whether the connection to the server has been lost.
+    ///
+    /// With reconnecting enabled, this only stays the case until the next request.
+    pub fn is_disconnected(&self) -> bool {
+        self.connection.read().unwrap().is_disconnected()
+    }
+
+    /// Current connection, after reconnecting if it has been lost and reconnecting is enabled.
+    fn connection(&self) -> Result<Arc<Connection>> {
+        let connection = self.connection.read().unwrap().clone();
+
+        if connection.is_disconnected() && self.reconnect.is_some() {
+            self.reconnect()
+        } else {
+            Ok(connection)
+        }
+    }
+
+    fn reconnect(&self) -> Result<Arc<Connection>> {
+        let _guard = self.reconnecting.lock().unwrap();
+
+        // Somebody else may have reconnected in the meantime
+        let connection = self.connection.read().unwrap().clone();
+        if !connection.is_disconnected() {
+            return Ok(connection);
+        }
+
+        let (connect, policy) = match (&self.connect, &self.reconnect) {
+            (Some(connect), Some(policy)) => (connect, policy),
+            _ => return Err(disconnected()),
+        };
+
+        let mut backoff = policy.initial_backoff;
+        let mut attempts = 0;
+
+        let connection = loop {
+            let ret = self.runtime.block_on(async {
+                Connection::establish(connect().await?, &self.credentials).await
+            });
+
+            attempts += 1;
+
+            match ret {
+                Ok(connection) => break Arc::new(connection),
+                Err(e) if policy.max_attempts.is_some_and(|max| attempts >= max) => return Err(e),
+                Err(_) => {}
+            }
+
+            std::thread::sleep(backoff);
+            backoff = core::cmp::min(backoff * 2, policy.max_backoff);
+        };
+
+        // Nobody may resolve handles until they are all valid on the new connection.
+        let mut handles = self.handles.lock().unwrap();
+
+        for handle in handles.values_mut() {
+            handle.remote = self
+                .request_on(
+                    &connection,
+                    FrontendFuncs::Open,
+                    None,
+                    handle.path.as_bytes(),
+                    |_, _| Err(malformed()),
+                )
+                .and_then(|ret| get_size(&mut ret.as_slice()))
+                .map(|remote| remote as usize)
+                .ok();
+        }
+
+        *self.connection.write().unwrap() = connection.clone();
+
+        Ok(connection)
+    }
+
+    /// Current connection, and the server's handle `handle` maps to on it.
+    fn resolve(&self, handle: usize) -> Result<(Arc<Connection>, usize)> {
+        // Handles get re-opened by reconnecting
+        self.connection()?;
+
+        let handles = self.handles.lock().unwrap();
+        let remote = handles
+            .get(&handle)
+            .and_then(|handle| handle.remote)
+            .ok_or_else(unknown_handle)?;
+
+        Ok((self.connection.read().unwrap().clone(), remote))
+    }
+
+    /// Perform a single request.
+    ///
+    /// If `handle` is given, the request operates on it, and the server's handle is prepended to
+    /// `body`.
+    ///
+    /// `on_frame` is invoked for every `Data` and `Fail` frame of the response. The result is the
+    /// output of the final frame.
+    fn request(
+        &self,
+        func: FrontendFuncs,
+        handle: Option<usize>,
+        body: &[u8],
+        on_frame: impl FnMut(FrameKind, &[u8]) -> Result<()>,
+    ) -> Result<Vec<u8>> {
+        // Checked up front, so that it takes precedence over invalid handles
+        if self.hello().ops & func.mask() == 0 {
+            return Err(unsupported());
+        }
+
+        let (connection, handle) = match handle {
+            Some(handle) => {
+                let (connection, remote) = self.resolve(handle)?;
+                (connection, Some(remote))
+            }
+            None => (self.connection()?, None),
+        };
+
+        self.request_on(&connection, func, handle, body, on_frame)
+    }
+
+    /// Perform a single request on the given connection, with `handle` being the server's one.
+    fn request_on(
+        &self,
+        connection: &Connection,
+        func: FrontendFuncs,
+        handle: Option<usize>,
+        body: &[u8],
+        mut on_frame: impl FnMut(FrameKind, &[u8]) -> Result<()>,
+    ) -> Result<Vec<u8>> {
+        if connection.hello.ops & func.mask() == 0 {
+            return Err(unsupported());
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, mut responses) = mpsc::unbounded_channel();
+
+        {
+            let mut pending = connection.shared.pending.lock().unwrap();
+            if connection.is_disconnected() {
+                return Err(disconnected());
+            }
+            pending.insert(id, sender);
+        }
+
+        let _guard = PendingGuard(&connection.shared, id);
+
+        let body = match handle {
+            Some(handle) => [&(handle as Size).to_le_bytes(), body].concat(),
+            None => body.to_vec(),
+        };
+
+        let compress = connection.hello.features & FEATURE_LZ4 != 0;
+
+        connection
+            .frames
+            .send(Frame::encode(id, func as u8, &body, compress))
+            .map_err(|_| disconnected())?;
+
+        let response = async {
+            while let Some(frame) = responses.recv().await {
+                let mut body = frame.body.as_slice();
+                match FrameKind::try_from(frame.kind)? {
+                    FrameKind::Done => {
+                        int_to_result(get_i32(&mut body)?)?;
+                        return Ok(body.to_vec());
+                    }
+                    kind => on_frame(kind, body)?,
+                }
+            }
+            Err(disconnected())
+        };
+
+        self.runtime.block_on(async {
+            match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, response)
+                    .await
+                    .unwrap_or(Err(Error(ErrorOrigin::Io, ErrorKind::Unknown))),
+                None => response.await,
+            }
+        })
+    }
+
+    /// Send a single batch of a read operation.
+    fn read_batch<'a, 'b>(
+        &self,
+        handle: usize,
+        req: &[u8],
+        mut bufs: SegmentTree<'b, &'b mut [u8]>,
+        out: &mut Option<&'a mut OpaqueCallback<'a, RWData<'b>>>,
+        out_fail: &mut Option<&'a mut OpaqueCallback<'a, RWFailData<'b>>>,
+    ) -> Result<()> {
+        self.request(FrontendFuncs::Read, Some(handle), req, |kind, mut body| {
+            let mut addr = get_size(&mut body)?;
+            match kind {
+                FrameKind::Data => {
+                    while !body.is_empty() {
+                        let buf = bufs.get(addr, body.len()).ok_or_else(malformed)?;
+                        let (data, rest) = body.split_at(buf.len());
+                        buf.copy_from_slice(data);
+                        opt_call(out.as_deref_mut(), CTup2(addr, buf.into()));
+                        addr += data.len() as Size;
+                        body = rest;
+                    }
+                }
+                _ => {
+                    let mut len = get_size(&mut body)? as usize;
+                    let err = get_fail_err(&mut body)?;
+                    while len > 0 {
+                        let buf = bufs.get(addr, len).ok_or_else(malformed)?;
+                        let blen = buf.len();
+                        opt_call(
+                            out_fail.as_deref_mut(),
+                            (CTup2(addr, buf.into()), err).into(),
+                        );
+                        addr += blen as Size;
+                        len -= blen;
+                    }
+                }
+            }
+            Ok(())
+        })
+        .map(|_| ())
+    }
+
+    /// Send a single batch of a write operation.
+    fn write_batch<'a, 'b>(
+        &self,
+        handle: usize,
+        req: &[u8],
+        mut bufs: SegmentTree<'b, &'b [u8]>,
+        out: &mut Option<&'a mut OpaqueCallback<'a, ROData<'b>>>,
+        out_fail: &mut Option<&'a mut OpaqueCallback<'a, ROFailData<'b>>>,
+    ) -> Result<()> {
+        self.request(FrontendFuncs::Write, Some(handle), req, |kind, mut body| {
+            let mut addr = get_size(&mut body)?;
+            let mut len = get_size(&mut body)? as usize;
+            let err = match kind {
+                FrameKind::Data => None,
+                _ => Some(get_fail_err(&mut body)?),
+            };
+            while len > 0 {
+                let buf = bufs.get(addr, len).ok_or_else(malformed)?;
+                let blen = buf.len();
+                match err {
+                    None => opt_call(out.as_deref_mut(), CTup2(addr, buf.into())),
+                    Some(err) => opt_call(
+                        out_fail.as_deref_mut(),
+                        (CTup2(addr, buf.into()), err).into(),
+                    ),
+                };
+                addr += blen as Size;
+                len -= blen;
+            }
+            Ok(())
+        })
+        .map(|_| ())
+    }
+}
+
+/// Read the non-zero error code of a failed piece of data.
+fn get_fail_err(buf: &mut &[u8]) -> Result<Error> {
+    NonZeroI32::new(get_i32(buf)?)
+        .map(Error::from_int_err)
+        .ok_or_else(malformed)
+}
+
+/// Size of the (address, length) header of every read or write entry.
+const ENTRY_HEADER_LEN: usize = 2 * core::mem::size_of::<Size>();
+
+impl Frontend for FilerClient {
+    /// Perform read operation on the given handle
+    fn read(&self, handle: usize, mut data: VecOps<RWData>) -> Result<()> {
+        let mut req = vec![];
+        let mut req_len = 0;
+        let mut bufs = SegmentTree::default();
+
+        for CTup2(mut addr, buf) in data.inp {
+            let mut buf = <&mut [u8]>::from(buf);
+
+            // Large reads are split up into multiple requests
+            while !buf.is_empty() {
+                if req_len >= MAX_IO_LEN {
+                    let bufs = core::mem::take(&mut bufs);
+                    self.read_batch(handle, &req, bufs, &mut data.out, &mut data.out_fail)?;
+                    req.clear();
+                    req_len = 0;
+                }
+
+                let len = core::cmp::min(buf.len(), MAX_IO_LEN - req_len);
+                let (cur, rest) = core::mem::take(&mut buf).split_at_mut(len);
+
+                put_size(&mut req, addr);
+                put_size(&mut req, len as Size);
+                bufs.add_seg(addr, cur);
+
+                req_len += ENTRY_HEADER_LEN + len;
+                addr += len as Size;
+                buf = rest;
+            }
+        }
+
+        if req_len > 0 {
+            self.read_batch(handle, &req, bufs, &mut data.out, &mut data.out_fail)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Perform write operation on the given handle.
+    fn write(&self, handle: usize, mut data: VecOps<ROData>) -> Result<()> {
+        let mut req = vec![];
+        let mut bufs = SegmentTree::default();
+
+        for CTup2(mut addr, buf) in data.inp {
+            let mut buf = <&[u8]>::from(buf);
+
+            // Large writes are split up into multiple requests
+            while !buf.is_empty() {
+                if req.len() >= MAX_IO_LEN {
+                    let bufs = core::mem::take(&mut bufs);
+                    self.write_batch(handle, &req, bufs, &mut data.out, &mut data.out_fail)?;
+                    req.clear();
+                }
+
+                let len = core::cmp::min(buf.len(), MAX_IO_LEN - req.len());
+                let (cur, rest) = buf.split_at(len);
+
+                put_size(&mut req, addr);
+                put_bytes(&mut req, cur);
+                bufs.add_seg(addr, cur);
+
+                addr += len as Size;
+                buf = rest;
+            }
+        }
+
+        if !req.is_empty() {
+            self.write_batch(handle, &req, bufs, &mut data.out, &mut data.out_fail)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Perform remote procedure call on the given handle.
+    fn rpc(&self, handle: usize, input: &[u8], output: &mut [u8]) -> Result<()> {
+        let mut req = vec![];
+        put_bytes(&mut req, input);
+        put_size(&mut req, output.len() as Size);
+
+        let ret = self.request(FrontendFuncs::Rpc, Some(handle), &req, |_, _| {
+            Err(malformed())
+        })?;
+
+        if ret.len() != output.len() {
+            return Err(malformed());
+        }
+
+        output.copy_from_slice(&ret);
+
+        Ok(())
+    }
+
+    /// Close an already open handle.
+    fn close(&self, handle: usize) -> Result<()> {
+        let remote = {
+            let mut handles = self.handles.lock().unwrap();
+            let handle = handles.remove(&handle).ok_or_else(unknown_handle)?;
+            handle
+                .remote
+                .map(|remote| (self.connection.read().unwrap().clone(), remote))
+        };
+
+        let (connection, remote) = match remote {
+            Some(remote) => remote,
+            // Nothing is open on the server's side
+            None => return Ok(()),
+        };
+
+        let ret = self.request_on(
+            &connection,
+            FrontendFuncs::Close,
+            Some(remote),
+            &[],
+            |_, _| Err(malformed()),
+        );
+
+        match ret {
+            // The server closes the handles of lost connections by itself
+            Err(e) if e == disconnected() => Ok(()),
+            ret => ret.map(|_| ()),
+        }
+    }
+
+    /// Open a leaf at the given path. The result is a handle.
+    fn open(&self, path: &str) -> Result<usize> {
+        let connection = self.connection()?;
+
+        let ret = self.request_on(
+            &connection,
+            FrontendFuncs::Open,
+            None,
+            path.as_bytes(),
+            |_, _| Err(malformed()),
+        )?;
+        let remote = get_size(&mut ret.as_slice())? as usize;
+
+        let mut handles = self.handles.lock().unwrap();
+
+        // The handle is gone if the connection got replaced in the meantime
+        if !Arc::ptr_eq(&connection, &self.connection.read().unwrap()) {
+            return Err(disconnected());
+        }
+
+        let handle = self.next_handle.fetch_add(1, Ordering::Relaxed);
+        handles.insert(
+            handle,
+            OpenHandle {
+                path: path.into(),
+                remote: Some(remote),
+            },
+        );
+
+        Ok(handle)
+    }
+
+    /// Get metadata of given path.
+    fn metadata(&self, path: &str) -> Result<NodeMetadata> {
+        let ret = self.request(FrontendFuncs::Metadata, None, path.as_bytes(), |_, _| {
+            Err(malformed())
+        })?;
+        get_metadata(&mut ret.as_slice())
+    }
+
+    /// List entries in the given path. It is a (name, is_branch) pair.
+    fn list(&self, path: &str, out: &mut OpaqueCallback<ListEntry>) -> Result<()> {
+        // The whole response has to be consumed, even if the callback does not want more.
+        let mut cont = true;
+
+        self.request(FrontendFuncs::List, None, path.as_bytes(), |_, mut body| {
+            let is_branch = get_u8(&mut body)? != 0;
+            let name = core::str::from_utf8(body).map_err(|_| malformed())?;
+            if cont {
+                cont = out.call(ListEntry::new(name.into(), is_branch));
+            }
+            Ok(())
+        })
+        .map(|_| ())
+    }
+}
+
+/// `Backend` forwarding all operations to a remote node.
+///
+/// This allows mounting remote nodes inside a `NodeBackend`. Returned handles are the ones of the
+/// `FilerClient`, `NodeBackend` maps them through `HandleMap::Forward`. Plugins are resolved on
+/// the remote side.
+pub struct RemoteBackend {
+    client: FilerClient,
+}
+
+impl RemoteBackend {
+    pub fn new(client: FilerClient) -> Self {
+        Self { client }
+    }
+
+    pub fn client(&self) -> &FilerClient {
+        &self.client
+    }
+}
+
+impl From<FilerClient> for RemoteBackend {
+    fn from(client: FilerClient) -> Self {
+        Self::new(client)
+    }
+}
+
+impl Backend for RemoteBackend {
+    fn read(&self, _: BackendStack, handle: usize, data: VecOps<RWData>) -> Result<()> {
+        self.client.read(handle, data)
+    }
+
+    fn write(&self, _: BackendStack, handle: usize, data: VecOps<ROData>) -> Result<()> {
+        self.client.write(handle, data)
+    }
+
+    fn rpc(&self, _: BackendStack, handle: usize, input: &[u8], output: &mut [u8]) -> Result<()> {
+        self.client.rpc(handle, input, output)
+    }
+
+    fn close(&self, _: BackendStack, handle: usize) -> Result<()> {
+        self.client.close(handle)
+    }
+
+    fn open(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<usize> {
+        self.client.open(path)
+    }
+
+    fn metadata(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<NodeMetadata> {
+        self.client.metadata(path)
+    }
+
+    fn list(
+        &self,
+        _: BackendStack,
+        path: &str,
+        _: &CPluginStore,
+        out: &mut OpaqueCallback<ListEntry>,
+    ) -> Result<()> {
+        self.client.list(path, out)
+    }
+}
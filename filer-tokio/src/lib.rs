@@ -1,69 +1,86 @@
-use cglue::arc::CArcSome;
-use cglue::callback::OpaqueCallback;
 use cglue::result::IntError;
-use cglue::slice::CSliceMut;
-use cglue::tuple::CTup2;
-use core::mem::size_of;
 use core::num::NonZeroI32;
 use filer::prelude::v1::*;
 
 use std::collections::BTreeMap;
 use std::io;
-use tokio::io::{AsyncRead, AsyncWrite, BufReader};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, DuplexStream, ReadHalf,
+    WriteHalf,
+};
 use tokio::net::{tcp, TcpListener, TcpStream};
-use tokio::runtime::{Handle, Runtime};
-use tokio::sync::Mutex;
-use tokio::task::JoinHandle;
+#[cfg(unix)]
+use tokio::net::{unix, UnixListener, UnixStream};
+use tokio::sync::{mpsc, Mutex};
 
-#[derive(Default)]
-struct FragmentBuffer<'a> {
-    bufs: Vec<Box<[u8]>>,
-    fragments: BTreeMap<usize, Vec<*mut u8>>,
-    _phantom: core::marker::PhantomData<&'a mut [u8]>,
+pub mod auth;
+pub mod client;
+pub mod server;
+
+pub use auth::{Authenticator, Identity, NoAuth, TokenAuth};
+
+pub use client::{FilerClient, ReconnectPolicy, RemoteBackend};
+pub use server::FilerServer;
+
+/// Upper bound for the body of any single frame received over the wire.
+///
+/// This prevents a misbehaving peer from making us allocate arbitrary amounts of memory.
+pub const MAX_BUF_LEN: usize = 1 << 26;
+
+/// Upper bound for the amount of data moved by a single read or write request.
+///
+/// Clients split larger operations into multiple requests.
+pub const MAX_IO_LEN: usize = MAX_BUF_LEN / 2;
+
+/// Byte slices that can be tracked by a `SegmentTree`.
+trait Segment<'a>: Sized {
+    fn into_raw(self) -> (*mut u8, usize);
+
+    /// # Safety
+    ///
+    /// `ptr` and `len` must describe a part of a slice previously passed through `into_raw`.
+    unsafe fn from_raw(ptr: *mut u8, len: usize) -> Self;
 }
 
-unsafe impl Send for FragmentBuffer<'_> {}
+impl<'a> Segment<'a> for &'a mut [u8] {
+    fn into_raw(self) -> (*mut u8, usize) {
+        (self.as_mut_ptr(), self.len())
+    }
 
-impl<'a> FragmentBuffer<'a> {
-    pub unsafe fn put_back(&mut self, fragment: *mut [u8]) {
-        let len = (*fragment).len();
-        let raw = (*fragment).as_mut_ptr();
-        self.fragments.entry(len).or_default().push(raw);
+    unsafe fn from_raw(ptr: *mut u8, len: usize) -> Self {
+        core::slice::from_raw_parts_mut(ptr, len)
     }
+}
 
-    pub fn get(&mut self, size: usize) -> &'a mut [u8] {
-        let fragment = if let Some((&sz, fragments)) = self.fragments.range_mut(size..).next() {
-            let frag = fragments.pop().unwrap();
-            if fragments.is_empty() {
-                self.fragments.remove(&sz);
-            }
-            if sz > size {
-                unsafe {
-                    self.put_back(core::slice::from_raw_parts_mut(frag.add(size), sz - size));
-                }
-            }
-            frag
-        } else {
-            let mut b = vec![0; size].into_boxed_slice();
-            let raw = b.as_mut_ptr();
-            self.bufs.push(b);
-            raw
-        };
-        unsafe { core::slice::from_raw_parts_mut(fragment, size) }
+impl<'a> Segment<'a> for &'a [u8] {
+    fn into_raw(self) -> (*mut u8, usize) {
+        (self.as_ptr() as *mut u8, self.len())
+    }
+
+    unsafe fn from_raw(ptr: *mut u8, len: usize) -> Self {
+        core::slice::from_raw_parts(ptr, len)
     }
 }
 
-#[derive(Default)]
-struct SegmentTree<'a> {
+/// Maps address ranges back to the caller's buffers.
+struct SegmentTree<'a, T: Segment<'a>> {
     segments: BTreeMap<Size, Vec<(*mut u8, usize)>>,
-    _phantom: core::marker::PhantomData<&'a mut [u8]>,
+    _phantom: core::marker::PhantomData<(&'a mut [u8], T)>,
 }
 
-unsafe impl Send for SegmentTree<'_> {}
+unsafe impl<'a, T: Segment<'a>> Send for SegmentTree<'a, T> {}
 
-impl<'a> SegmentTree<'a> {
-    pub fn get(&mut self, start: Size, len: usize) -> Option<&'a mut [u8]> {
+impl<'a, T: Segment<'a>> Default for SegmentTree<'a, T> {
+    fn default() -> Self {
+        Self {
+            segments: Default::default(),
+            _phantom: Default::default(),
+        }
+    }
+}
+
+impl<'a, T: Segment<'a>> SegmentTree<'a, T> {
+    pub fn get(&mut self, start: Size, len: usize) -> Option<T> {
         let mut iter = self.segments.range_mut(..=start);
 
         while let Some((&seg_start, segs)) = iter.next_back() {
@@ -76,9 +93,7 @@ impl<'a> SegmentTree<'a> {
 
                     // Add anything on the left
                     if seg_start < start {
-                        self.add_seg(seg_start, unsafe {
-                            core::slice::from_raw_parts_mut(seg, (start - seg_start) as usize)
-                        });
+                        self.add_raw(seg_start, seg, (start - seg_start) as usize);
                     } else {
                         // Otherwise cleanup, if this was the last segment we took
                         if segs.is_empty() {
@@ -90,16 +105,11 @@ impl<'a> SegmentTree<'a> {
 
                     // Add anything over the end
                     if seg_end > end {
-                        self.add_seg(end, unsafe {
-                            core::slice::from_raw_parts_mut(seg.add(len), (seg_end - end) as usize)
-                        });
+                        self.add_raw(end, unsafe { seg.add(len) }, (seg_end - end) as usize);
                     }
 
                     return Some(unsafe {
-                        core::slice::from_raw_parts_mut(
-                            seg,
-                            (core::cmp::min(seg_end, end) - start) as usize,
-                        )
+                        T::from_raw(seg, (core::cmp::min(seg_end, end) - start) as usize)
                     });
                 }
             }
@@ -108,41 +118,409 @@ impl<'a> SegmentTree<'a> {
         None
     }
 
-    pub fn add_seg(&mut self, start: Size, seg: &'a mut [u8]) {
-        self.segments
-            .entry(start)
-            .or_default()
-            .push((seg.as_mut_ptr(), seg.len()))
+    pub fn add_seg(&mut self, start: Size, seg: T) {
+        let (ptr, len) = seg.into_raw();
+        self.add_raw(start, ptr, len)
+    }
+
+    fn add_raw(&mut self, start: Size, ptr: *mut u8, len: usize) {
+        self.segments.entry(start).or_default().push((ptr, len))
     }
 }
 
+/// Functions of the `Frontend` trait that can be invoked over the wire.
+///
+/// These are used as the kind of request frames.
 #[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum FrontendFuncs {
+    /// Perform read operation on the given handle.
     Read = 0,
+    /// Perform write operation on the given handle.
     Write,
+    /// Perform remote procedure call on the given handle.
     Rpc,
+    /// Close an already open handle.
     Close,
+    /// Open a leaf at the given path. The result is a handle.
     Open,
+    /// Get metadata of given path.
     Metadata,
+    /// List entries in the given path.
     List,
-    Highest, // fn read(&self, handle: usize, data: VecOps<RWData>) -> Result<()>;
-             // /// Perform write operation on the given handle.
-             // fn write(&self, handle: usize, data: VecOps<ROData>) -> Result<()>;
-             // /// Perform remote procedure call on the given handle.
-             // fn rpc(&self, handle: usize, input: &[u8], output: &mut [u8]) -> Result<()>;
-             // /// Close an already open handle.
-             // fn close(&self, handle: usize) -> Result<()>;
-             // /// Open a leaf at the given path. The result is a handle.
-             // fn open(&self, path: &str) -> Result<usize>;
-             // /// Get metadata of given path.
-             // fn metadata(&self, path: &str) -> Result<NodeMetadata>;
-             // /// List entries in the given path. It is a (name, is_branch) pair.
-             // fn list(&self, path: &str, out: &mut OpaqueCallback<ListEntry>) -> Result<()>;
+    Highest,
+}
+
+impl FrontendFuncs {
+    /// Bit of this function within the operation mask of a `Hello`.
+    pub const fn mask(self) -> u64 {
+        1 << self as u8
+    }
+}
+
+/// Magic bytes starting every connection.
+pub const PROTOCOL_MAGIC: [u8; 4] = *b"FLRT";
+
+/// Version of the wire protocol.
+///
+/// Must be bumped whenever the encoding of any request or response changes. Peers with different
+/// versions refuse to talk to each other.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Mask of all `FrontendFuncs` this build can serve and issue.
+pub const SUPPORTED_OPS: u64 = (1 << FrontendFuncs::Highest as u8) - 1;
+
+/// Feature bit allowing frame bodies to be compressed with LZ4, see `Frame`.
+pub const FEATURE_LZ4: u64 = 1 << 0;
+
+/// Mask of optional protocol features this build supports.
+pub const SUPPORTED_FEATURES: u64 = FEATURE_LZ4;
+
+/// Frame bodies shorter than this are never compressed, as it is not worth the effort.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Bit set in the kind of frames whose body is compressed.
+const COMPRESSED_KIND: u8 = 0x80;
+
+/// Handshake message sent by both sides when a connection is established.
+///
+/// The client sends its `Hello` first, and the server responds with its own. The capabilities of
+/// the connection are the intersection of both. Afterwards the client authenticates, see
+/// `send_credentials`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+    /// Mask of supported `FrontendFuncs`.
+    pub ops: u64,
+    /// Mask of supported optional features.
+    pub features: u64,
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            ops: SUPPORTED_OPS,
+            features: SUPPORTED_FEATURES,
+        }
+    }
+}
+
+impl Hello {
+    pub async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin)) -> io::Result<()> {
+        let mut buf = PROTOCOL_MAGIC.to_vec();
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&self.ops.to_le_bytes());
+        buf.extend_from_slice(&self.features.to_le_bytes());
+        writer.write_all(&buf).await?;
+        writer.flush().await
+    }
+
+    /// Read a `Hello` from the peer. Fails if the peer does not speak the protocol at all.
+    pub async fn read_from(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).await?;
+
+        if magic != PROTOCOL_MAGIC {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        Ok(Self {
+            version: reader.read_u32_le().await?,
+            ops: reader.read_u64_le().await?,
+            features: reader.read_u64_le().await?,
+        })
+    }
+
+    /// Capabilities shared by both peers, or `VersionMismatch` if they can not communicate.
+    pub fn negotiate(&self, remote: &Self) -> Result<Self> {
+        if self.version != remote.version {
+            return Err(Error(ErrorOrigin::Io, ErrorKind::VersionMismatch));
+        }
+
+        Ok(Self {
+            version: self.version,
+            ops: self.ops & remote.ops,
+            features: self.features & remote.features,
+        })
+    }
+}
+
+/// Upper bound for the size of credentials presented by clients.
+pub const MAX_CREDENTIALS_LEN: usize = 4096;
+
+/// Present credentials to the server, and wait for it to accept them.
+///
+/// Rejected clients receive an `InvalidArgument` error, and get disconnected.
+async fn send_credentials(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    credentials: &[u8],
+) -> Result<()> {
+    let mut buf = vec![];
+    put_bytes(&mut buf, credentials);
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+    int_to_result(stream.read_i32_le().await?)
+}
+
+/// Receive credentials sent through `send_credentials`.
+async fn receive_credentials(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+    let len = reader.read_u64_le().await? as usize;
+
+    if len > MAX_CREDENTIALS_LEN {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+fn rejected() -> Error {
+    Error(ErrorOrigin::Io, ErrorKind::InvalidArgument)
+}
+
+/// Kinds of response frames.
+///
+/// Every request is answered by any number of `Data` and `Fail` frames, followed by a single
+/// `Done` frame carrying the error code of the operation.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FrameKind {
+    /// Final frame of a response. Contains the error code followed by the operation output.
+    Done = 0,
+    /// Successfully processed piece of data, or a single list entry.
+    Data,
+    /// Piece of data that failed to be processed, along with its error code.
+    Fail,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = Error;
+
+    fn try_from(kind: u8) -> Result<Self> {
+        match kind {
+            0 => Ok(Self::Done),
+            1 => Ok(Self::Data),
+            2 => Ok(Self::Fail),
+            _ => Err(malformed()),
+        }
+    }
+}
+
+/// A single message on the wire.
+///
+/// Frames are encoded as `[id: u64][kind: u8][len: u64][body]`. Requests use `FrontendFuncs` as
+/// their kind, while responses use `FrameKind`. Responses carry the ID of the request they answer,
+/// thus multiple requests can be in flight on a single connection, and their responses may arrive
+/// in any order.
+///
+/// If `FEATURE_LZ4` has been negotiated, large bodies may be sent compressed, which is signaled by
+/// setting the top bit of the kind. This is transparent to the users of `Frame`.
+#[derive(Debug)]
+pub struct Frame {
+    pub id: u64,
+    pub kind: u8,
+    pub body: Vec<u8>,
+}
+
+impl Frame {
+    /// Encode a frame, compressing its body if `compress` is set and it pays off.
+    pub fn encode(id: u64, kind: u8, body: &[u8], compress: bool) -> Vec<u8> {
+        let compressed = if compress && body.len() >= COMPRESSION_THRESHOLD {
+            Some(lz4_flex::compress_prepend_size(body)).filter(|c| c.len() < body.len())
+        } else {
+            None
+        };
+
+        let (kind, body) = match &compressed {
+            Some(compressed) => (kind | COMPRESSED_KIND, compressed.as_slice()),
+            None => (kind, body),
+        };
+
+        let mut buf = Vec::with_capacity(17 + body.len());
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.push(kind);
+        put_bytes(&mut buf, body);
+        buf
+    }
+
+    pub async fn read_from(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Self> {
+        let id = reader.read_u64_le().await?;
+        let kind = reader.read_u8().await?;
+        let len = reader.read_u64_le().await? as usize;
+
+        if len > MAX_BUF_LEN {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).await?;
+
+        if kind & COMPRESSED_KIND != 0 {
+            return Ok(Self {
+                id,
+                kind: kind & !COMPRESSED_KIND,
+                body: decompress(&body)?,
+            });
+        }
+
+        Ok(Self { id, kind, body })
+    }
+}
+
+fn decompress(body: &[u8]) -> io::Result<Vec<u8>> {
+    let size = get_array::<4>(&mut &body[..]).map_err(|_| io::ErrorKind::InvalidData)?;
+
+    // Check the size up front, since it determines how much memory gets allocated.
+    if u32::from_le_bytes(size) as usize > MAX_BUF_LEN {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    lz4_flex::decompress_size_prepended(body).map_err(|_| io::ErrorKind::InvalidData.into())
+}
+
+/// Write out encoded frames until the channel is closed, or the stream fails.
+///
+/// The frames are sent by a single task, so that no partially written frame can ever end up on
+/// the wire, even if the producer of the frame gets cancelled.
+async fn write_frames(
+    writer: impl AsyncWrite + Unpin,
+    mut frames: mpsc::UnboundedReceiver<Vec<u8>>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+
+    while let Some(frame) = frames.recv().await {
+        writer.write_all(&frame).await?;
+
+        // Batch up everything that is already queued before flushing
+        while let Ok(frame) = frames.try_recv() {
+            writer.write_all(&frame).await?;
+        }
+
+        writer.flush().await?;
+    }
+
+    Ok(())
+}
+
+/// Convert a result into the error code sent over the wire. Zero means success.
+fn result_to_int<T>(res: &Result<T>) -> i32 {
+    match res {
+        Ok(_) => 0,
+        Err(e) => e.into_int_err().get(),
+    }
+}
+
+/// Convert an error code received over the wire back into a result.
+fn int_to_result(err: i32) -> Result<()> {
+    NonZeroI32::new(err)
+        .map(|v| Err(Error::from_int_err(v)))
+        .unwrap_or(Ok(()))
+}
+
+/// Error for frame bodies that do not follow the protocol.
+fn malformed() -> Error {
+    Error(ErrorOrigin::Io, ErrorKind::InvalidArgument)
+}
+
+fn put_size(buf: &mut Vec<u8>, val: Size) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    put_size(buf, data.len() as Size);
+    buf.extend_from_slice(data);
+}
+
+fn put_metadata(buf: &mut Vec<u8>, meta: &NodeMetadata) {
+    buf.extend_from_slice(&[
+        meta.is_branch as u8,
+        meta.has_read as u8,
+        meta.has_write as u8,
+        meta.has_rpc as u8,
+    ]);
+    put_size(buf, meta.size);
+}
+
+fn get_slice<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(malformed());
+    }
+    let (ret, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(ret)
+}
+
+fn get_array<const N: usize>(buf: &mut &[u8]) -> Result<[u8; N]> {
+    Ok(get_slice(buf, N)?.try_into().unwrap())
+}
+
+fn get_u8(buf: &mut &[u8]) -> Result<u8> {
+    Ok(get_array::<1>(buf)?[0])
+}
+
+fn get_i32(buf: &mut &[u8]) -> Result<i32> {
+    get_array(buf).map(i32::from_le_bytes)
+}
+
+fn get_size(buf: &mut &[u8]) -> Result<Size> {
+    get_array(buf).map(Size::from_le_bytes)
+}
+
+fn get_bytes<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = get_size(buf)? as usize;
+    get_slice(buf, len)
+}
+
+fn get_metadata(buf: &mut &[u8]) -> Result<NodeMetadata> {
+    let flags = get_array::<4>(buf)?;
+    Ok(NodeMetadata {
+        is_branch: flags[0] != 0,
+        has_read: flags[1] != 0,
+        has_write: flags[2] != 0,
+        has_rpc: flags[3] != 0,
+        size: get_size(buf)?,
+    })
+}
+
+/// Address a node is served on, or connected to.
+///
+/// This is written as either a TCP address, optionally prefixed by `tcp://`, or the path of a
+/// unix domain socket prefixed by `unix:`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeAddr<'a> {
+    Tcp(&'a str),
+    Unix(&'a str),
+}
+
+impl<'a> NodeAddr<'a> {
+    pub fn parse(addr: &'a str) -> io::Result<Self> {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            return Ok(Self::Unix(path));
+        }
+
+        // The HTTP gateway speaks a different protocol altogether
+        if addr.starts_with("http://") || addr.starts_with("https://") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{addr} is an HTTP address, the node is reached with a tcp:// or unix: address"
+                ),
+            ));
+        }
+
+        Ok(Self::Tcp(
+            addr.strip_prefix("tcp://")
+                .unwrap_or(addr)
+                .trim_end_matches('/'),
+        ))
+    }
 }
 
 pub trait SplitStream {
-    type OwnedReadHalf: AsyncRead + Send + Unpin;
-    type OwnedWriteHalf: AsyncWrite + Send + Unpin;
+    type OwnedReadHalf: AsyncRead + Send + Unpin + 'static;
+    type OwnedWriteHalf: AsyncWrite + Send + Unpin + 'static;
 
     fn into_split(self) -> (Self::OwnedReadHalf, Self::OwnedWriteHalf);
 }
@@ -156,6 +534,25 @@ impl SplitStream for TcpStream {
     }
 }
 
+#[cfg(unix)]
+impl SplitStream for UnixStream {
+    type OwnedReadHalf = unix::OwnedReadHalf;
+    type OwnedWriteHalf = unix::OwnedWriteHalf;
+
+    fn into_split(self) -> (Self::OwnedReadHalf, Self::OwnedWriteHalf) {
+        UnixStream::into_split(self)
+    }
+}
+
+impl SplitStream for DuplexStream {
+    type OwnedReadHalf = ReadHalf<DuplexStream>;
+    type OwnedWriteHalf = WriteHalf<DuplexStream>;
+
+    fn into_split(self) -> (Self::OwnedReadHalf, Self::OwnedWriteHalf) {
+        tokio::io::split(self)
+    }
+}
+
 #[async_trait::async_trait]
 pub trait Listener {
     type Stream: SplitStream + Unpin + Send + 'static;
@@ -174,251 +571,810 @@ impl Listener for TcpListener {
     }
 }
 
-pub struct FilerClient<T> {
-    stream: Mutex<T>,
-    runtime: Runtime,
+#[cfg(unix)]
+#[async_trait::async_trait]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+    type SocketAddr = unix::SocketAddr;
+
+    async fn accept(&self) -> io::Result<(Self::Stream, Self::SocketAddr)> {
+        UnixListener::accept(self).await
+    }
+}
+
+/// In-process listener accepting `DuplexStream`s created by a `DuplexConnector`.
+pub struct DuplexListener {
+    incoming: Mutex<mpsc::UnboundedReceiver<DuplexStream>>,
+}
+
+/// Creates in-process connections to a `DuplexListener`.
+#[derive(Clone)]
+pub struct DuplexConnector {
+    sender: mpsc::UnboundedSender<DuplexStream>,
+    max_buf_size: usize,
+}
+
+impl DuplexConnector {
+    /// Create a new connection. The other end is handed to the listener.
+    pub fn connect(&self) -> io::Result<DuplexStream> {
+        let (local, remote) = tokio::io::duplex(self.max_buf_size);
+        self.sender
+            .send(remote)
+            .map_err(|_| io::ErrorKind::ConnectionRefused)?;
+        Ok(local)
+    }
+}
+
+/// Create an in-process listener, and a connector to it.
+///
+/// `max_buf_size` is the amount of bytes that can be buffered in each direction of a connection.
+pub fn duplex_listener(max_buf_size: usize) -> (DuplexListener, DuplexConnector) {
+    let (sender, incoming) = mpsc::unbounded_channel();
+    (
+        DuplexListener {
+            incoming: Mutex::new(incoming),
+        },
+        DuplexConnector {
+            sender,
+            max_buf_size,
+        },
+    )
 }
 
-impl<T: AsyncRead + AsyncWrite + Unpin> Frontend for FilerClient<T> {
-    /// Perform read operation on the given handle
-    fn read(&self, _handle: usize, mut data: VecOps<RWData>) -> Result<()> {
-        self.runtime.block_on(async {
-            let mut stream = self.stream.lock().await;
-            let mut bufs = SegmentTree::default();
+#[async_trait::async_trait]
+impl Listener for DuplexListener {
+    type Stream = DuplexStream;
+    type SocketAddr = ();
+
+    async fn accept(&self) -> io::Result<(Self::Stream, Self::SocketAddr)> {
+        let stream = self.incoming.lock().await.recv().await;
+        stream
+            .map(|s| (s, ()))
+            .ok_or_else(|| io::ErrorKind::NotConnected.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cglue::slice::CSliceMut;
+    use cglue::tuple::CTup2;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::runtime::Runtime;
+
+    fn connect(node: CArcSome<Node>) -> (Runtime, FilerClient) {
+        let runtime = Runtime::new().unwrap();
+        let (listener, connector) = duplex_listener(1 << 16);
+        runtime.spawn(FilerServer::new(listener, node).run());
+        (
+            runtime,
+            FilerClient::connect_duplex(&connector, &[]).unwrap(),
+        )
+    }
+
+    fn list(client: &impl Frontend, path: &str) -> Result<Vec<(String, bool)>> {
+        let mut entries = vec![];
+        let cb = &mut |entry: ListEntry| {
+            entries.push((entry.name.to_string(), entry.is_branch));
+            true
+        };
+        client.list(path, &mut cb.into())?;
+        entries.sort();
+        Ok(entries)
+    }
 
-            stream.write_all(&[FrontendFuncs::Read as u8]).await?;
+    /// Simple in-memory backend exposing a single readable and writeable leaf.
+    ///
+    /// RPC calls with empty input block until something gets written to the leaf.
+    #[derive(Default)]
+    struct BufBackend {
+        data: Mutex<Vec<u8>>,
+        opened: AtomicUsize,
+        closed: AtomicUsize,
+    }
 
-            for CTup2(addr, buf) in data.inp {
-                stream.write_all(&(addr as Size).to_le_bytes()).await?;
-                stream.write_all(&(buf.len() as Size).to_le_bytes()).await?;
-                stream.write_all(&buf).await?;
-                bufs.add_seg(addr, buf.into());
+    impl Backend for BufBackend {
+        fn read(&self, _: BackendStack, _: usize, mut data: VecOps<RWData>) -> Result<()> {
+            let buf = self.data.lock().unwrap();
+            for CTup2(off, mut to) in data.inp {
+                let (off, len) = (off as usize, to.len());
+                if off + len <= buf.len() {
+                    to.copy_from_slice(&buf[off..(off + len)]);
+                    opt_call(data.out.as_deref_mut(), CTup2(off as Size, to));
+                } else {
+                    let e = Error(ErrorOrigin::Read, ErrorKind::OutOfBounds);
+                    opt_call(
+                        data.out_fail.as_deref_mut(),
+                        (CTup2(off as Size, to), e).into(),
+                    );
+                }
             }
+            Ok(())
+        }
 
-            stream.write_all(&(0 as Size).to_le_bytes()).await?;
-            stream.write_all(&(0 as Size).to_le_bytes()).await?;
-
-            loop {
-                let mut idx = [0u8];
-                stream.read_exact(&mut idx).await?;
-                match idx[0] {
-                    0 => {
-                        let mut err = [0u8; size_of::<i32>()];
-                        stream.read_exact(&mut err).await?;
-                        return NonZeroI32::new(i32::from_le_bytes(err))
-                            .map(|v| Err(Error::from_int_err(v)))
-                            .unwrap_or(Ok(()));
-                    }
-                    1 => {
-                        let mut addr = [0u8; size_of::<Size>()];
-                        let mut buf_len = [0u8; size_of::<Size>()];
-                        stream.read_exact(&mut addr).await?;
-                        stream.read_exact(&mut buf_len).await?;
-                        let mut addr = Size::from_le_bytes(addr);
-                        let mut buf_len = Size::from_le_bytes(buf_len) as usize;
-                        while buf_len > 0 {
-                            let buf = bufs.get(addr, buf_len).unwrap();
-                            let blen = buf.len();
-                            stream.read_exact(buf).await?;
-                            opt_call(data.out.as_deref_mut(), CTup2(addr, buf.into()));
-                            addr += blen as Size;
-                            buf_len -= blen;
-                        }
-                    }
-                    2 => {
-                        let mut addr = [0u8; size_of::<Size>()];
-                        let mut buf_len = [0u8; size_of::<Size>()];
-                        let mut err = [0u8; size_of::<i32>()];
-                        stream.read_exact(&mut addr).await?;
-                        stream.read_exact(&mut buf_len).await?;
-                        stream.read_exact(&mut err).await?;
-                        let mut addr = Size::from_le_bytes(addr);
-                        let mut buf_len = Size::from_le_bytes(buf_len) as usize;
-                        let _err = i32::from_le_bytes(err) as usize;
-                        while buf_len > 0 {
-                            let buf = bufs.get(addr, buf_len).unwrap();
-                            let blen = buf.len();
-                            opt_call(data.out.as_deref_mut(), CTup2(addr, buf.into()));
-                            addr += blen as Size;
-                            buf_len -= blen;
-                        }
-                    }
-                    _ => {}
+        fn write(&self, _: BackendStack, _: usize, mut data: VecOps<ROData>) -> Result<()> {
+            let mut buf = self.data.lock().unwrap();
+            for CTup2(off, from) in data.inp {
+                let off = off as usize;
+                if buf.len() < off + from.len() {
+                    buf.resize(off + from.len(), 0);
                 }
+                buf[off..(off + from.len())].copy_from_slice(&from);
+                opt_call(data.out.as_deref_mut(), CTup2(off as Size, from));
             }
-        })
+            Ok(())
+        }
+
+        fn rpc(&self, _: BackendStack, _: usize, input: &[u8], output: &mut [u8]) -> Result<()> {
+            while input.is_empty() && self.data.lock().unwrap().is_empty() {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            for (o, i) in output.iter_mut().zip(input.iter().rev()) {
+                *o = *i;
+            }
+            Ok(())
+        }
+
+        fn close(&self, _: BackendStack, _: usize) -> Result<()> {
+            self.closed.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn open(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<usize> {
+            if path == "buf" {
+                Ok(self.opened.fetch_add(1, Ordering::SeqCst))
+            } else {
+                Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound))
+            }
+        }
+
+        fn metadata(&self, _: BackendStack, path: &str, _: &CPluginStore) -> Result<NodeMetadata> {
+            match path {
+                "" => Ok(NodeMetadata::branch()),
+                "buf" => Ok(NodeMetadata {
+                    has_read: true,
+                    has_write: true,
+                    has_rpc: true,
+                    size: self.data.lock().unwrap().len() as Size,
+                    ..Default::default()
+                }),
+                _ => Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+            }
+        }
+
+        fn list(
+            &self,
+            _: BackendStack,
+            _: &str,
+            _: &CPluginStore,
+            out: &mut OpaqueCallback<ListEntry>,
+        ) -> Result<()> {
+            let _ = out.call(ListEntry::new("buf".into(), false));
+            Ok(())
+        }
     }
-    /// Perform write operation on the given handle.
-    fn write(&self, _handle: usize, _data: VecOps<ROData>) -> Result<()> {
-        todo!()
+
+    #[test]
+    pub fn remote_list() {
+        let (_server, client) = connect(cloudflow::create_node());
+
+        let root = list(&client, "").unwrap();
+        assert!(root.contains(&("connector".to_string(), true)));
+        assert!(root.contains(&("os".to_string(), true)));
+
+        let connector = list(&client, "connector").unwrap();
+        assert!(connector.contains(&("new".to_string(), false)));
+        assert!(connector.contains(&("rm".to_string(), false)));
+
+        assert!(list(&client, "nonexistent").is_err());
     }
-    /// Perform remote procedure call on the given handle.
-    fn rpc(&self, _handle: usize, _input: &[u8], _output: &mut [u8]) -> Result<()> {
-        todo!()
+
+    #[test]
+    pub fn remote_metadata() {
+        let (_server, client) = connect(cloudflow::create_node());
+
+        assert!(client.metadata("").unwrap().is_branch);
+        assert!(client.metadata("os").unwrap().is_branch);
+        assert!(!client.metadata("os/new").unwrap().is_branch);
+
+        let err = client.metadata("connector/nonexistent").unwrap_err();
+        assert_eq!(err.1, ErrorKind::NotFound);
     }
-    /// Close an already open handle.
-    fn close(&self, _handle: usize) -> Result<()> {
-        todo!()
+
+    #[test]
+    pub fn remote_open_close() {
+        let (_server, client) = connect(cloudflow::create_node());
+
+        let handle = client.open("connector/new").unwrap();
+        assert!(client.close(handle).is_ok());
+
+        let err = client.open("connector/nonexistent").unwrap_err();
+        assert_eq!(err.1, ErrorKind::NotFound);
     }
-    /// Open a leaf at the given path. The result is a handle.
-    fn open(&self, _path: &str) -> Result<usize> {
-        todo!()
+
+    #[test]
+    pub fn remote_errors() {
+        let (_server, client) = connect(cloudflow::create_node());
+
+        let handle = client.open("connector/new").unwrap();
+
+        let mut failed = vec![];
+        let out_fail = &mut |data: ROFailData| {
+            let (CTup2(_, buf), e) = data.into();
+            failed.push((buf.to_vec(), e));
+            true
+        };
+        let mut out_fail = out_fail.into();
+        let inp = &mut core::iter::once(CTup2(0, CSliceRef::from(&b"test nonexistent"[..])));
+        let ops = VecOps {
+            inp: inp.into(),
+            out: None,
+            out_fail: Some(&mut out_fail),
+        };
+        assert!(client.write(handle, ops).is_ok());
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, b"test nonexistent");
+
+        let mut buf = [0u8; 4];
+        let inp = &mut core::iter::once(CTup2(0, CSliceMut::from(&mut buf[..])));
+        let err = client.read(handle, inp.into()).unwrap_err();
+        assert_eq!(err, Error(ErrorOrigin::Read, ErrorKind::NotImplemented));
+
+        let err = client.rpc(handle, &[], &mut []).unwrap_err();
+        assert_eq!(err, Error(ErrorOrigin::Rpc, ErrorKind::NotImplemented));
+
+        assert!(client.close(handle).is_ok());
     }
-    /// Get metadata of given path.
-    fn metadata(&self, _path: &str) -> Result<NodeMetadata> {
-        todo!()
-    }
-    /// List entries in the given path. It is a (name, is_branch) pair.
-    fn list(&self, _path: &str, _out: &mut OpaqueCallback<ListEntry>) -> Result<()> {
-        todo!()
-    }
-}
-
-pub struct FilerServer<T: Listener> {
-    listener: T,
-    clients: Vec<(JoinHandle<io::Result<()>>, T::SocketAddr)>,
-    node: CArcSome<Node>,
-}
-
-impl<T: Listener> FilerServer<T> {
-    pub async fn run(mut self) -> io::Result<()> {
-        loop {
-            let (socket, addr) = self.listener.accept().await?;
-
-            let node = self.node.clone();
-
-            let handle = tokio::spawn(async move {
-                let (reader, mut writer) = socket.into_split();
-                let mut reader = BufReader::new(reader);
-
-                // In a loop, read data from the socket and write the data back.
-                loop {
-                    let cmd = &mut [0u8];
-                    reader.read_exact(cmd).await?;
-                    let cmd = cmd[0];
-
-                    if cmd < FrontendFuncs::Highest as u8 {
-                        use FrontendFuncs::*;
-                        match unsafe { core::mem::transmute::<_, FrontendFuncs>(cmd) } {
-                            Read => {
-                                let bufs = FragmentBuffer::default();
-                                let bufs = Mutex::new(bufs);
-                                let writer = Mutex::new(&mut writer);
-
-                                let mut fh = [0u8; size_of::<Size>()];
-                                reader.read_exact(&mut fh).await?;
-                                let fh = Size::from_le_bytes(fh) as usize;
-
-                                let handle = Handle::current();
-
-                                let iter = &mut core::iter::from_fn(|| {
-                                    let mut addr = [0u8; size_of::<Size>()];
-
-                                    let buf = handle
-                                        .block_on(async {
-                                            let mut size = [0u8; size_of::<Size>()];
-                                            reader.read_exact(&mut addr).await?;
-                                            let sz = reader
-                                                .read_exact(&mut size)
-                                                .await
-                                                .map(|_| Size::from_le_bytes(size))
-                                                .and_then(|sz| {
-                                                    if sz > 0 {
-                                                        Ok(sz)
-                                                    } else {
-                                                        Err(io::Error::from(io::ErrorKind::Other))
-                                                    }
-                                                })?;
-
-                                            io::Result::Ok(bufs.lock().await.get(sz as usize))
-                                        })
-                                        .ok()?;
-
-                                    let addr = Size::from_le_bytes(addr);
-
-                                    Some(CTup2(addr, CSliceMut::from(buf)))
-                                });
-
-                                let out = &mut |CTup2(addr, buf): RWData| {
-                                    let _ = handle.block_on(async {
-                                        let mut writer = writer.lock().await;
-                                        writer.write_all(&[1]).await?;
-                                        writer.write_all(&Size::to_le_bytes(addr)).await?;
-                                        writer
-                                            .write_all(&Size::to_le_bytes(buf.len() as _))
-                                            .await?;
-                                        writer.write_all(&buf).await?;
-                                        unsafe {
-                                            bufs.lock().await.put_back(<&mut [u8]>::from(buf))
-                                        };
-                                        io::Result::Ok(())
-                                    });
-                                    true
-                                };
-
-                                let out_fail = &mut |fdata: RWFailData| {
-                                    let (CTup2(addr, buf), e) = fdata.into();
-                                    let _ = handle.block_on(async {
-                                        let mut writer = writer.lock().await;
-                                        writer.write_all(&[2]).await?;
-                                        writer.write_all(&Size::to_le_bytes(addr)).await?;
-                                        writer
-                                            .write_all(&Size::to_le_bytes(buf.len() as _))
-                                            .await?;
-                                        writer
-                                            .write_all(&e.into_int_err().get().to_le_bytes())
-                                            .await?;
-                                        unsafe {
-                                            bufs.lock().await.put_back(<&mut [u8]>::from(buf))
-                                        };
-                                        io::Result::Ok(())
-                                    });
-                                    true
-                                };
-
-                                let mut out = out.into();
-                                let mut out_fail = out_fail.into();
-
-                                let ops = VecOps {
-                                    inp: iter.into(),
-                                    out: Some(&mut out),
-                                    out_fail: Some(&mut out_fail),
-                                };
-
-                                let err = tokio::task::block_in_place(|| node.read(fh, ops));
-
-                                let writer = writer.into_inner();
-                                writer.write_all(&[0]).await?;
-                                let err = match err {
-                                    Ok(_) => 0,
-                                    Err(e) => e.into_int_err().get(),
-                                };
-                                writer.write_all(&err.to_le_bytes()).await?;
-                            }
-                            Write => {}
-                            _ => {}
-                        }
-                    }
 
-                    /*let n = match reader.read(&mut buf).await {
-                        // socket closed
-                        Ok(n) if n == 0 => return,
-                        Ok(n) => n,
-                        Err(e) => {
-                            eprintln!("failed to read from socket; err = {:?}", e);
-                            return;
-                        }
-                    };
+    fn request(id: u64, func: FrontendFuncs, body: &[u8]) -> Vec<u8> {
+        Frame::encode(id, func as u8, body, false)
+    }
 
-                    // Write the data back
-                    if let Err(e) = writer.write_all(&buf[0..n]).await {
-                        eprintln!("failed to write to socket; err = {:?}", e);
-                        return;
-                    }*/
-                }
+    /// Connect without a `FilerClient`, and open `buf` through the request with ID 1.
+    async fn raw_open(connector: &DuplexConnector) -> (DuplexStream, Size) {
+        let mut stream = connector.connect().unwrap();
+
+        Hello::default().write_to(&mut stream).await.unwrap();
+        Hello::read_from(&mut stream).await.unwrap();
+        send_credentials(&mut stream, &[]).await.unwrap();
+
+        let open = request(1, FrontendFuncs::Open, b"buf");
+        stream.write_all(&open).await.unwrap();
+        let done = Frame::read_from(&mut stream).await.unwrap();
+        let handle = get_size(&mut &done.body[4..]).unwrap();
+
+        (stream, handle)
+    }
+
+    #[test]
+    pub fn remote_read_overflow() {
+        let runtime = Runtime::new().unwrap();
+        let (listener, connector) = duplex_listener(1 << 16);
+        runtime.spawn(FilerServer::new(listener, Node::new(BufBackend::default()).into()).run());
+
+        runtime.block_on(async {
+            let (mut stream, handle) = raw_open(&connector).await;
+
+            // Lengths only adding up to more than the address space
+            let mut body = vec![];
+            put_size(&mut body, handle);
+            for len in [1, Size::MAX] {
+                put_size(&mut body, 0);
+                put_size(&mut body, len);
+            }
+            stream
+                .write_all(&request(2, FrontendFuncs::Read, &body))
+                .await
+                .unwrap();
+
+            let done = Frame::read_from(&mut stream).await.unwrap();
+            assert_eq!((done.id, done.kind), (2, FrameKind::Done as u8));
+            let err = i32::from_le_bytes(done.body[..4].try_into().unwrap());
+            assert_eq!(int_to_result(err), Err(malformed()));
+        });
+    }
+
+    #[test]
+    pub fn remote_read_write() {
+        let (_server, client) = connect(Node::new(BufBackend::default()).into());
+
+        assert_eq!(list(&client, "").unwrap(), vec![("buf".to_string(), false)]);
+
+        let mut cursor = client.open_cursor("buf").unwrap();
+        cursor.write_all(b"hello filer").unwrap();
+        cursor.seek(SeekFrom::Start(6)).unwrap();
+
+        let mut buf = [0u8; 5];
+        cursor.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"filer");
+
+        // Reading past the end is reported through out_fail
+        let mut buf = [0u8; 4];
+        assert_eq!(cursor.read(&mut buf).unwrap(), 0);
+
+        assert_eq!(client.metadata("buf").unwrap().size, 11);
+
+        let handle = client.open("buf").unwrap();
+        let mut output = [0u8; 3];
+        client.rpc(handle, b"abc", &mut output).unwrap();
+        assert_eq!(&output, b"cba");
+        client.close(handle).unwrap();
+    }
+
+    #[test]
+    pub fn remote_backend() {
+        let (server, client) = connect(cloudflow::create_node());
+
+        let backend = NodeBackend::default();
+        backend.add_backend("hostA", RemoteBackend::from(client));
+        let node: CArcSome<Node> = Node::new(backend).into();
+
+        assert_eq!(list(&node, "").unwrap(), vec![("hostA".to_string(), true)]);
+
+        let connector = list(&node, "hostA/connector").unwrap();
+        assert!(connector.contains(&("new".to_string(), false)));
+        assert!(node.metadata("hostA/os").unwrap().is_branch);
+
+        let handle = node.open("hostA/connector/new").unwrap();
+        assert!(node.close(handle).is_ok());
+
+        // Once the remote goes away, requests fail instead of blocking
+        drop(server);
+
+        assert!(list(&node, "hostA/connector").is_err());
+        let err = node.metadata("hostA/os").unwrap_err();
+        assert_eq!(err, Error(ErrorOrigin::Io, ErrorKind::Uninitialized));
+    }
+
+    #[test]
+    pub fn remote_tcp() {
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+        runtime.spawn(FilerServer::new(listener, cloudflow::create_node()).run());
+
+        let client = FilerClient::connect(addr, &[]).unwrap();
+        assert!(list(&client, "")
+            .unwrap()
+            .contains(&("os".to_string(), true)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn remote_unix() {
+        let path = std::env::temp_dir().join(format!("filer-tokio-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime
+            .block_on(async { UnixListener::bind(&path) })
+            .unwrap();
+        runtime.spawn(FilerServer::new(listener, cloudflow::create_node()).run());
+
+        let client = FilerClient::connect_unix(&path, &[]).unwrap();
+        assert!(list(&client, "")
+            .unwrap()
+            .contains(&("os".to_string(), true)));
+
+        drop(runtime);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn remote_reconnect() {
+        let path = std::env::temp_dir().join(format!("filer-tokio-{}-re.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let runtime = Runtime::new().unwrap();
+        let backend = Arc::new(BufBackend::default());
+
+        let serve = || {
+            let _ = std::fs::remove_file(&path);
+            let listener = runtime
+                .block_on(async { UnixListener::bind(&path) })
+                .unwrap();
+            let node = Node::new(backend.clone()).into();
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let server = runtime.spawn(FilerServer::new(listener, node).run_until(async {
+                let _ = stopped.await;
+            }));
+            (stop, server)
+        };
+
+        let (stop, server) = serve();
+
+        let client = FilerClient::connect_unix(&path, &[])
+            .unwrap()
+            .with_reconnect(ReconnectPolicy {
+                max_attempts: Some(50),
+                initial_backoff: std::time::Duration::from_millis(1),
+                max_backoff: std::time::Duration::from_millis(20),
             });
 
-            self.clients.push((handle, addr));
+        let other = client.open("buf").unwrap();
+        let handle = client.open("buf").unwrap();
+        client.close(other).unwrap();
+
+        // Restart the server
+        stop.send(()).unwrap();
+        runtime.block_on(server).unwrap().unwrap();
+
+        let start = std::time::Instant::now();
+        while !client.is_disconnected() {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let (stop, server) = serve();
+
+        // The handle keeps working, even though the server handle behind it has changed
+        let mut out = [0; 3];
+        client.rpc(handle, b"abc", &mut out).unwrap();
+        assert_eq!(&out, b"cba");
+        assert_eq!(backend.opened.load(Ordering::SeqCst), 3);
+
+        assert_eq!(
+            client.close(other).unwrap_err(),
+            Error(ErrorOrigin::Node, ErrorKind::NotFound)
+        );
+        client.close(handle).unwrap();
+        assert_eq!(backend.closed.load(Ordering::SeqCst), 3);
+
+        // Reconnecting gives up once the server is gone for good
+        stop.send(()).unwrap();
+        runtime.block_on(server).unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let err = client.open("buf").unwrap_err();
+        assert_eq!(err.0, ErrorOrigin::Io);
+    }
+
+    #[test]
+    pub fn handshake_version_mismatch() {
+        let runtime = Runtime::new().unwrap();
+
+        // Server speaking a newer protocol version
+        let (listener, connector) = duplex_listener(1 << 16);
+        runtime.spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            Hello::read_from(&mut stream).await?;
+            let hello = Hello {
+                version: PROTOCOL_VERSION + 1,
+                ..Default::default()
+            };
+            hello.write_to(&mut stream).await
+        });
+
+        let err = FilerClient::connect_duplex(&connector, &[]).err().unwrap();
+        assert_eq!(err, Error(ErrorOrigin::Io, ErrorKind::VersionMismatch));
+
+        // Client speaking an older protocol version
+        let (listener, connector) = duplex_listener(1 << 16);
+        runtime.spawn(FilerServer::new(listener, cloudflow::create_node()).run());
+
+        let mut stream = connector.connect().unwrap();
+        runtime.block_on(async {
+            let hello = Hello {
+                version: PROTOCOL_VERSION - 1,
+                ..Default::default()
+            };
+            hello.write_to(&mut stream).await.unwrap();
+            assert_eq!(
+                Hello::read_from(&mut stream).await.unwrap(),
+                Hello::default()
+            );
+
+            // The server hangs up afterwards
+            let err = stream.read_u8().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        });
+    }
+
+    #[test]
+    pub fn handshake_unsupported_ops() {
+        let runtime = Runtime::new().unwrap();
+
+        // Server that is unable to perform remote procedure calls
+        let (listener, connector) = duplex_listener(1 << 16);
+        runtime.spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            Hello::read_from(&mut stream).await?;
+            let hello = Hello {
+                ops: SUPPORTED_OPS & !FrontendFuncs::Rpc.mask(),
+                ..Default::default()
+            };
+            hello.write_to(&mut stream).await?;
+            receive_credentials(&mut stream).await?;
+            stream.write_i32_le(0).await?;
+            // Keep the connection open
+            stream.read_u8().await
+        });
+
+        let client = FilerClient::connect_duplex(&connector, &[]).unwrap();
+        let err = client.rpc(0, &[], &mut []).unwrap_err();
+        assert_eq!(err, Error(ErrorOrigin::Io, ErrorKind::NotSupported));
+        assert!(!client.is_disconnected());
+    }
+
+    #[test]
+    pub fn remote_concurrent() {
+        let (_server, client) = connect(Node::new(BufBackend::default()).into());
+
+        std::thread::scope(|s| {
+            let client = &client;
+            let handle = client.open("buf").unwrap();
+
+            // Blocks until the write below goes through
+            let blocked = s.spawn(move || client.rpc(handle, &[], &mut []));
+
+            assert_eq!(list(client, "").unwrap(), vec![("buf".to_string(), false)]);
+
+            let mut cursor = client.open_cursor("buf").unwrap();
+            cursor.write_all(b"unblock").unwrap();
+
+            blocked.join().unwrap().unwrap();
+            client.close(handle).unwrap();
+        });
+    }
+
+    #[test]
+    pub fn remote_in_flight_limit() {
+        let backend = Arc::new(BufBackend::default());
+        let (_server, client) = connect(Node::new(backend.clone()).into());
+        let handle = client.open("buf").unwrap();
+        let wait = || std::thread::sleep(std::time::Duration::from_millis(100));
+
+        std::thread::scope(|s| {
+            let client = &client;
+
+            // Every one of them blocks until something gets written to the buffer
+            let blocked = (0..server::MAX_IN_FLIGHT)
+                .map(|_| s.spawn(move || client.rpc(handle, &[], &mut [])))
+                .collect::<Vec<_>>();
+            wait();
+
+            // Not even read by the server until one of the requests above finishes
+            let listed = s.spawn(move || list(client, ""));
+            wait();
+            assert!(!listed.is_finished());
+
+            backend.data.lock().unwrap().push(0);
+
+            for rpc in blocked {
+                rpc.join().unwrap().unwrap();
+            }
+            assert_eq!(
+                listed.join().unwrap().unwrap(),
+                vec![("buf".to_string(), false)]
+            );
+        });
+    }
+
+    #[test]
+    pub fn remote_large_io() {
+        let (_server, client) = connect(Node::new(BufBackend::default()).into());
+        assert_ne!(client.hello().features & FEATURE_LZ4, 0);
+
+        // Large enough to be split into multiple requests
+        let data = (0..(MAX_IO_LEN + MAX_IO_LEN / 2))
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+
+        let mut cursor = client.open_cursor("buf").unwrap();
+        cursor.write_all(&data).unwrap();
+        cursor.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut buf = vec![0; data.len()];
+        cursor.read_exact(&mut buf).unwrap();
+        assert!(buf == data);
+    }
+
+    #[test]
+    pub fn remote_auth() {
+        let runtime = Runtime::new().unwrap();
+        let (listener, connector) = duplex_listener(1 << 16);
+
+        let auth = TokenAuth::new()
+            .with_token("admin-token", Identity::new("admin"))
+            .with_token("guest-token", Identity::new("guest").read_only());
+
+        let node = Node::new(BufBackend::default()).into();
+        runtime.spawn(
+            FilerServer::new(listener, node)
+                .with_authenticator(auth)
+                .run(),
+        );
+
+        for credentials in [&b""[..], b"wrong-token"] {
+            let err = FilerClient::connect_duplex(&connector, credentials)
+                .err()
+                .unwrap();
+            assert_eq!(err, Error(ErrorOrigin::Io, ErrorKind::InvalidArgument));
+        }
+
+        let guest = FilerClient::connect_duplex(&connector, b"guest-token").unwrap();
+        assert_eq!(list(&guest, "").unwrap(), vec![("buf".to_string(), false)]);
+        let mut cursor = guest.open_cursor("buf").unwrap();
+        assert!(cursor.write_all(b"guest").is_err());
+
+        let admin = FilerClient::connect_duplex(&connector, b"admin-token").unwrap();
+        let mut cursor = admin.open_cursor("buf").unwrap();
+        cursor.write_all(b"admin").unwrap();
+    }
+
+    #[test]
+    pub fn remote_handle_cleanup() {
+        let runtime = Runtime::new().unwrap();
+        let (listener, connector) = duplex_listener(1 << 16);
+
+        let backend = Arc::new(BufBackend::default());
+        let node = Node::new(backend.clone()).into();
+        runtime.spawn(FilerServer::new(listener, node).run());
+
+        let client = FilerClient::connect_duplex(&connector, &[]).unwrap();
+        let handle = client.open("buf").unwrap();
+        client.open("buf").unwrap();
+
+        // Handles of other clients are off-limits
+        let other = FilerClient::connect_duplex(&connector, &[]).unwrap();
+        let err = other.close(handle).unwrap_err();
+        assert_eq!(err, Error(ErrorOrigin::Node, ErrorKind::NotFound));
+
+        client.close(handle).unwrap();
+        assert_eq!(backend.closed.load(Ordering::SeqCst), 1);
+
+        // The remaining handle gets closed once the client goes away
+        drop(client);
+
+        let start = std::time::Instant::now();
+        while backend.closed.load(Ordering::SeqCst) != 2 {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    pub fn remote_cleanup_waits_for_requests() {
+        let runtime = Runtime::new().unwrap();
+        let (listener, connector) = duplex_listener(1 << 16);
+
+        let backend = Arc::new(BufBackend::default());
+        runtime.spawn(FilerServer::new(listener, Node::new(backend.clone()).into()).run());
+
+        runtime.block_on(async {
+            let wait = || tokio::time::sleep(std::time::Duration::from_millis(100));
+            let (mut stream, handle) = raw_open(&connector).await;
+
+            // Blocks until something gets written to the buffer
+            let mut body = vec![];
+            put_size(&mut body, handle);
+            put_bytes(&mut body, &[]);
+            put_size(&mut body, 0);
+            stream
+                .write_all(&request(2, FrontendFuncs::Rpc, &body))
+                .await
+                .unwrap();
+            wait().await;
+
+            // The request still in progress keeps using the handle after the client is gone
+            drop(stream);
+            wait().await;
+            assert_eq!(backend.closed.load(Ordering::SeqCst), 0);
+
+            backend.data.lock().unwrap().push(0);
+
+            let start = std::time::Instant::now();
+            while backend.closed.load(Ordering::SeqCst) != 1 {
+                assert!(start.elapsed() < std::time::Duration::from_secs(5));
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        });
+    }
+
+    #[test]
+    pub fn remote_shutdown() {
+        let runtime = Runtime::new().unwrap();
+        let (listener, connector) = duplex_listener(1 << 16);
+
+        let backend = Arc::new(BufBackend::default());
+        let node = Node::new(backend.clone()).into();
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = runtime.spawn(
+            FilerServer::new(listener, node)
+                .with_shutdown_timeout(std::time::Duration::from_millis(100))
+                .run_until(async {
+                    let _ = stopped.await;
+                }),
+        );
+
+        let client = FilerClient::connect_duplex(&connector, &[]).unwrap();
+        let handle = client.open("buf").unwrap();
+        client.open("buf").unwrap();
+
+        std::thread::scope(|s| {
+            let client = &client;
+            // Blocks until the buffer has data, outliving the shutdown timeout
+            let rpc = s.spawn(move || client.rpc(handle, &[], &mut [0; 4]));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+
+            stop.send(()).unwrap();
+            runtime.block_on(server).unwrap().unwrap();
+
+            let err = rpc.join().unwrap().unwrap_err();
+            assert_eq!(err, Error(ErrorOrigin::Io, ErrorKind::Uninitialized));
+        });
+
+        assert_eq!(backend.closed.load(Ordering::SeqCst), 2);
+        assert!(client.is_disconnected());
+        assert!(FilerClient::connect_duplex(&connector, &[]).is_err());
+
+        // Let the stuck request finish
+        backend.data.lock().unwrap().push(0);
+    }
+
+    #[test]
+    pub fn frame_compression() {
+        let runtime = Runtime::new().unwrap();
+        let decode = |frame: &[u8]| runtime.block_on(Frame::read_from(&mut &frame[..])).unwrap();
+
+        let zeros = vec![0; 1 << 16];
+        let frame = Frame::encode(1, FrameKind::Data as u8, &zeros, true);
+        assert!(frame.len() < zeros.len());
+        assert_eq!(frame[8] & COMPRESSED_KIND, COMPRESSED_KIND);
+
+        let decoded = decode(&frame);
+        assert_eq!(decoded.id, 1);
+        assert_eq!(decoded.kind, FrameKind::Data as u8);
+        assert!(decoded.body == zeros);
+
+        // Small, incompressible, or not negotiated bodies are sent as is
+        let mut state = 0x2545f4914f6cdd1du64;
+        let noise = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+
+        for (body, compress) in [
+            (&zeros[..16], true),
+            (&noise[..], true),
+            (&zeros[..], false),
+        ] {
+            let frame = Frame::encode(2, FrameKind::Fail as u8, body, compress);
+            assert_eq!(frame.len(), 17 + body.len());
+            assert_eq!(decode(&frame).body, body);
         }
+
+        // Compressed bodies claiming to expand beyond the frame limit are rejected
+        let mut bomb = 0u64.to_le_bytes().to_vec();
+        bomb.push(COMPRESSED_KIND);
+        put_bytes(&mut bomb, &(MAX_BUF_LEN as u32 + 1).to_le_bytes());
+        assert!(runtime.block_on(Frame::read_from(&mut &bomb[..])).is_err());
+    }
+
+    #[test]
+    pub fn parse_node_addr() {
+        assert_eq!(
+            NodeAddr::parse("127.0.0.1:8000").unwrap(),
+            NodeAddr::Tcp("127.0.0.1:8000")
+        );
+        assert_eq!(
+            NodeAddr::parse("tcp://127.0.0.1:8000/").unwrap(),
+            NodeAddr::Tcp("127.0.0.1:8000")
+        );
+        assert_eq!(
+            NodeAddr::parse("unix:/run/cloudflow.sock").unwrap(),
+            NodeAddr::Unix("/run/cloudflow.sock")
+        );
+
+        let err = NodeAddr::parse("http://127.0.0.1:8000").unwrap_err();
+        assert!(err.to_string().contains("HTTP address"));
     }
 }
@@ -0,0 +1,517 @@
+use crate::*;
+
+use cglue::result::IntError;
+use cglue::slice::{CSliceMut, CSliceRef};
+use cglue::tuple::CTup2;
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Time given to requests in progress to finish once the server shuts down.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upper bound for the requests of a single client in progress at once.
+///
+/// Further requests are not read until one of them finishes.
+pub const MAX_IN_FLIGHT: usize = 64;
+
+/// Serves a `Node` to `FilerClient`s connecting through the given listener.
+///
+/// Every request is processed on tokio's blocking thread pool, so up to `MAX_IN_FLIGHT` requests
+/// of a single client can be in progress at once.
+///
+/// By default every client is accepted, use `with_authenticator` to restrict access.
+pub struct FilerServer<T: Listener> {
+    listener: T,
+    clients: Vec<(JoinHandle<io::Result<()>>, T::SocketAddr)>,
+    node: CArcSome<Node>,
+    auth: Arc<dyn Authenticator>,
+    shutdown_timeout: Duration,
+}
+
+impl<T: Listener> FilerServer<T> {
+    pub fn new(listener: T, node: CArcSome<Node>) -> Self {
+        Self {
+            listener,
+            clients: vec![],
+            node,
+            auth: Arc::new(NoAuth),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        }
+    }
+
+    /// Verify the credentials of every connecting client with the given authenticator.
+    pub fn with_authenticator(self, auth: impl Authenticator + 'static) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            ..self
+        }
+    }
+
+    /// Set how long requests in progress may take to finish once the server shuts down.
+    pub fn with_shutdown_timeout(self, shutdown_timeout: Duration) -> Self {
+        Self {
+            shutdown_timeout,
+            ..self
+        }
+    }
+
+    pub async fn run(self) -> io::Result<()> {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Serve clients until `shutdown` completes.
+    ///
+    /// Upon shutdown no more connections are accepted, and clients' requests are no longer read.
+    /// Requests in progress are given the shutdown timeout to finish, after which the handles left
+    /// open by every client are closed, and this function returns.
+    ///
+    /// If the listener fails, the error is returned right away, and the clients already connected
+    /// keep being served in the background.
+    pub async fn run_until(mut self, shutdown: impl Future<Output = ()>) -> io::Result<()> {
+        let (stop, stopped) = watch::channel(None);
+
+        tokio::pin!(shutdown);
+
+        loop {
+            let (socket, addr) = tokio::select! {
+                ret = self.listener.accept() => match ret {
+                    Ok(accepted) => accepted,
+                    Err(e) => return Err(e),
+                },
+                _ = &mut shutdown => break,
+            };
+
+            // Reap clients that have disconnected in the meantime
+            self.clients.retain(|(handle, _)| !handle.is_finished());
+
+            let node = self.node.clone();
+            let auth = self.auth.clone();
+
+            let handle = tokio::spawn(serve_client(node, auth, socket, stopped.clone()));
+
+            self.clients.push((handle, addr));
+        }
+
+        // Nobody is waiting for the new connections any more.
+        drop(self.listener);
+
+        let _ = stop.send(Some(Instant::now() + self.shutdown_timeout));
+
+        for (handle, _) in self.clients {
+            let _ = handle.await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Shutdown signal of the server, carrying the deadline for requests in progress.
+type Shutdown = watch::Receiver<Option<Instant>>;
+
+async fn shutdown_requested(shutdown: &mut Shutdown) {
+    if shutdown.changed().await.is_err() {
+        // The server went away without shutting down, so keep serving.
+        std::future::pending().await
+    }
+}
+
+async fn serve_client(
+    node: CArcSome<Node>,
+    auth: Arc<dyn Authenticator>,
+    socket: impl SplitStream,
+    mut shutdown: Shutdown,
+) -> io::Result<()> {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let client = tokio::select! {
+        client = accept_client(&*auth, &mut reader, &mut writer) => client?,
+        _ = shutdown_requested(&mut shutdown) => return Ok(()),
+    };
+
+    let client = match client {
+        Some(client) => client,
+        None => return Ok(()),
+    };
+
+    let (frames, outgoing) = mpsc::unbounded_channel();
+    let mut writer = tokio::spawn(write_frames(writer, outgoing));
+
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+
+    let ret = loop {
+        // Stop reading requests while too many of them are in progress
+        let permit = tokio::select! {
+            permit = in_flight.clone().acquire_owned() => {
+                permit.expect("the semaphore is never closed")
+            }
+            _ = shutdown_requested(&mut shutdown) => break Ok(()),
+        };
+
+        let frame = tokio::select! {
+            frame = Frame::read_from(&mut reader) => frame,
+            _ = shutdown_requested(&mut shutdown) => break Ok(()),
+        };
+
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => break Err(e),
+        };
+
+        if frame.kind >= FrontendFuncs::Highest as u8 {
+            break Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let node = node.clone();
+        let response = Response {
+            id: frame.id,
+            frames: frames.clone(),
+            client: client.clone(),
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let func = unsafe { core::mem::transmute::<u8, FrontendFuncs>(frame.kind) };
+            response.process(&node, func, &frame.body)
+        });
+    };
+
+    // Let the requests still in progress finish sending their responses, unless the server is
+    // shutting down and they are taking too long.
+    drop(frames);
+
+    let finished = {
+        let finished = async {
+            // Every permit being back means no request is in progress any more
+            let _ = in_flight.acquire_many(MAX_IN_FLIGHT as u32).await;
+            let _ = (&mut writer).await;
+        };
+        tokio::pin!(finished);
+
+        loop {
+            let deadline = *shutdown.borrow_and_update();
+
+            match deadline {
+                Some(deadline) => {
+                    break tokio::time::timeout_at(deadline, &mut finished)
+                        .await
+                        .is_ok()
+                }
+                None => tokio::select! {
+                    _ = &mut finished => break true,
+                    _ = shutdown_requested(&mut shutdown) => {}
+                },
+            }
+        }
+    };
+
+    if !finished {
+        writer.abort();
+    }
+
+    // Nobody is going to close the handles left open by the client. Requests still stuck past the
+    // deadline are refused to open any more of them.
+    let handles = client.handles.lock().unwrap().take().unwrap_or_default();
+
+    if !handles.is_empty() {
+        let _ = tokio::task::spawn_blocking(move || {
+            for handle in handles {
+                let _ = node.close(handle);
+            }
+        })
+        .await;
+    }
+
+    ret
+}
+
+/// Perform the handshake and authenticate the client.
+///
+/// Returns `None` if the connection is to be dropped.
+async fn accept_client(
+    auth: &dyn Authenticator,
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> io::Result<Option<Arc<Client>>> {
+    let hello = Hello::read_from(reader).await?;
+    Hello::default().write_to(writer).await?;
+
+    // The client rejects the connection as well once it sees our version.
+    let hello = match Hello::default().negotiate(&hello) {
+        Ok(hello) => hello,
+        Err(_) => return Ok(None),
+    };
+
+    let credentials = receive_credentials(reader).await?;
+
+    let client = match auth.authenticate(&credentials) {
+        Some(identity) => Arc::new(Client {
+            identity,
+            compress: hello.features & FEATURE_LZ4 != 0,
+            handles: Mutex::new(Some(HashSet::new())),
+        }),
+        None => {
+            let err = rejected().into_int_err().get();
+            writer.write_i32_le(err).await?;
+            writer.flush().await?;
+            return Ok(None);
+        }
+    };
+
+    writer.write_i32_le(0).await?;
+    writer.flush().await?;
+
+    Ok(Some(client))
+}
+
+/// State of a single connection.
+struct Client {
+    identity: Identity,
+    /// Whether frame bodies may be compressed.
+    compress: bool,
+    /// Handles opened by the client. Any other handles are off-limits to it.
+    ///
+    /// Taken once the connection gets cleaned up, after which no handles can be opened.
+    handles: Mutex<Option<HashSet<usize>>>,
+}
+
+impl Client {
+    fn check_handle(&self, handle: usize) -> Result<usize> {
+        let handles = self.handles.lock().unwrap();
+
+        if handles.as_ref().is_some_and(|h| h.contains(&handle)) {
+            Ok(handle)
+        } else {
+            Err(Error(ErrorOrigin::Node, ErrorKind::NotFound))
+        }
+    }
+}
+
+/// Sends the frames answering a single request.
+struct Response {
+    id: u64,
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+    /// Client that issued the request.
+    client: Arc<Client>,
+}
+
+impl Response {
+    fn send(&self, kind: FrameKind, body: &[u8]) {
+        // If the client is gone there is nobody to answer to
+        let _ = self.frames.send(Frame::encode(
+            self.id,
+            kind as u8,
+            body,
+            self.client.compress,
+        ));
+    }
+
+    /// Perform the request, and send the final frame with its result.
+    fn process(&self, node: &CArcSome<Node>, func: FrontendFuncs, body: &[u8]) {
+        let mut out = 0i32.to_le_bytes().to_vec();
+
+        use FrontendFuncs::*;
+        let ret = match func {
+            Write | Rpc if self.client.identity.read_only => {
+                Err(Error(ErrorOrigin::Node, ErrorKind::ReadOnly))
+            }
+            Read => self.read(node, body),
+            Write => self.write(node, body),
+            Rpc => self.rpc(node, body, &mut out),
+            Close => self.close(node, body),
+            Open => self.open(node, body, &mut out),
+            Metadata => metadata(node, body, &mut out),
+            List => self.list(node, body),
+            Highest => unreachable!(),
+        };
+
+        if ret.is_err() {
+            out.truncate(4);
+            out.copy_from_slice(&result_to_int(&ret).to_le_bytes());
+        }
+
+        self.send(FrameKind::Done, &out);
+    }
+
+    fn send_fail(&self, addr: Size, len: usize, err: Error) {
+        let mut body = vec![];
+        put_size(&mut body, addr);
+        put_size(&mut body, len as Size);
+        body.extend_from_slice(&err.into_int_err().get().to_le_bytes());
+        self.send(FrameKind::Fail, &body);
+    }
+
+    fn read(&self, node: &CArcSome<Node>, mut body: &[u8]) -> Result<()> {
+        let fh = self.client.check_handle(get_size(&mut body)? as usize)?;
+
+        let mut entries = vec![];
+        let mut total = 0usize;
+
+        while !body.is_empty() {
+            let addr = get_size(&mut body)?;
+            let len = get_size(&mut body)? as usize;
+            total = match total.checked_add(len) {
+                Some(total) if total <= MAX_IO_LEN => total,
+                _ => return Err(malformed()),
+            };
+            entries.push((addr, len));
+        }
+
+        let mut buf = vec![0; total];
+        let mut rest = buf.as_mut_slice();
+
+        let mut slices = vec![];
+
+        for (addr, len) in entries {
+            let (cur, next) = core::mem::take(&mut rest).split_at_mut(len);
+            slices.push(CTup2(addr, CSliceMut::from(cur)));
+            rest = next;
+        }
+
+        let iter = &mut slices.into_iter();
+
+        let out = &mut |CTup2(addr, buf): RWData| {
+            let mut body = Vec::with_capacity(8 + buf.len());
+            put_size(&mut body, addr);
+            body.extend_from_slice(&buf);
+            self.send(FrameKind::Data, &body);
+            true
+        };
+
+        let out_fail = &mut |fdata: RWFailData| {
+            let (CTup2(addr, buf), e) = fdata.into();
+            self.send_fail(addr, buf.len(), e);
+            true
+        };
+
+        let mut out = out.into();
+        let mut out_fail = out_fail.into();
+
+        let ops = VecOps {
+            inp: iter.into(),
+            out: Some(&mut out),
+            out_fail: Some(&mut out_fail),
+        };
+
+        node.read(fh, ops)
+    }
+
+    fn write(&self, node: &CArcSome<Node>, mut body: &[u8]) -> Result<()> {
+        let fh = self.client.check_handle(get_size(&mut body)? as usize)?;
+
+        let mut chunks = vec![];
+
+        while !body.is_empty() {
+            let addr = get_size(&mut body)?;
+            chunks.push(CTup2(addr, CSliceRef::from(get_bytes(&mut body)?)));
+        }
+
+        let iter = &mut chunks.into_iter();
+
+        let out = &mut |CTup2(addr, buf): ROData| {
+            let mut body = vec![];
+            put_size(&mut body, addr);
+            put_size(&mut body, buf.len() as Size);
+            self.send(FrameKind::Data, &body);
+            true
+        };
+
+        let out_fail = &mut |fdata: ROFailData| {
+            let (CTup2(addr, buf), e) = fdata.into();
+            self.send_fail(addr, buf.len(), e);
+            true
+        };
+
+        let mut out = out.into();
+        let mut out_fail = out_fail.into();
+
+        let ops = VecOps {
+            inp: iter.into(),
+            out: Some(&mut out),
+            out_fail: Some(&mut out_fail),
+        };
+
+        node.write(fh, ops)
+    }
+
+    fn list(&self, node: &CArcSome<Node>, body: &[u8]) -> Result<()> {
+        let path = core::str::from_utf8(body).map_err(|_| malformed())?;
+
+        let cb = &mut |entry: ListEntry| {
+            let mut body = vec![entry.is_branch as u8];
+            body.extend_from_slice(entry.name.as_bytes());
+            self.send(FrameKind::Data, &body);
+            true
+        };
+
+        node.list(path, &mut cb.into())
+    }
+
+    fn rpc(&self, node: &CArcSome<Node>, mut body: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let fh = self.client.check_handle(get_size(&mut body)? as usize)?;
+        let input = get_bytes(&mut body)?;
+        let len = get_size(&mut body)? as usize;
+
+        if len > MAX_IO_LEN {
+            return Err(malformed());
+        }
+
+        let mut output = vec![0; len];
+        node.rpc(fh, input, &mut output)?;
+        out.extend_from_slice(&output);
+
+        Ok(())
+    }
+
+    fn close(&self, node: &CArcSome<Node>, mut body: &[u8]) -> Result<()> {
+        let fh = get_size(&mut body)? as usize;
+
+        let removed = match self.client.handles.lock().unwrap().as_mut() {
+            Some(handles) => handles.remove(&fh),
+            None => false,
+        };
+
+        if !removed {
+            return Err(Error(ErrorOrigin::Node, ErrorKind::NotFound));
+        }
+
+        node.close(fh)
+    }
+
+    fn open(&self, node: &CArcSome<Node>, body: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let path = core::str::from_utf8(body).map_err(|_| malformed())?;
+        let fh = node.open(path)?;
+
+        let tracked = self
+            .client
+            .handles
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(|handles| handles.insert(fh))
+            .is_some();
+
+        // The connection got cleaned up in the meantime, so nobody would close the handle
+        if !tracked {
+            let _ = node.close(fh);
+            return Err(Error(ErrorOrigin::Node, ErrorKind::Unknown));
+        }
+
+        put_size(out, fh as Size);
+        Ok(())
+    }
+}
+
+fn metadata(node: &CArcSome<Node>, body: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let path = core::str::from_utf8(body).map_err(|_| malformed())?;
+    put_metadata(out, &node.metadata(path)?);
+    Ok(())
+}
@@ -12,6 +12,8 @@ use cglue::trait_group::c_void;
 
 use dashmap::{mapref::one::Ref, DashMap};
 
+use std::sync::{Arc, Mutex, MutexGuard};
+
 #[derive(StableAbi)]
 #[repr(C)]
 pub struct ListEntry {
@@ -25,67 +27,189 @@ impl ListEntry {
     }
 }
 
-fn map_exists<T>(entries: &DashMap<String, T>, name: &str) -> bool {
-    ["new", "rm"].contains(&name) || entries.contains_key(name)
+/// Contents of a read-only leaf, produced whenever the leaf is opened.
+type TextFn = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// Read-only leaves next to the entries, by name.
+type TextLeaves = Vec<(&'static str, TextFn)>;
+
+fn map_exists<T>(entries: &DashMap<String, T>, leaves: &TextLeaves, name: &str) -> bool {
+    ["new", "rm"].contains(&name)
+        || leaves.iter().any(|(n, _)| *n == name)
+        || entries.contains_key(name)
 }
 
-fn map_insert<T>(entries: &DashMap<String, T>, name: &str, entry: T) -> bool {
-    if name.contains('/') || map_exists(entries, name) {
+fn map_insert<T>(entries: &DashMap<String, T>, leaves: &TextLeaves, name: &str, entry: T) -> bool {
+    if name.contains('/') || map_exists(entries, leaves, name) {
         false
     } else {
         entries.insert(name.into(), entry).is_none()
     }
 }
 
-fn map_checked_insert<T>(entries: &DashMap<String, T>, name: &str, entry: T) -> Result<()> {
-    if !map_insert(entries, name, entry) {
+fn map_checked_insert<T>(
+    entries: &DashMap<String, T>,
+    leaves: &TextLeaves,
+    name: &str,
+    entry: T,
+) -> Result<()> {
+    if !map_insert(entries, leaves, name, entry) {
         Err(Error(ErrorOrigin::Backend, ErrorKind::AlreadyExists))
     } else {
         Ok(())
     }
 }
 
-struct NewHandler<T: 'static, C: 'static>(
-    CArcSome<DashMap<String, T>>,
-    CArc<C>,
-    fn(&str, &CArc<C>) -> Result<T>,
-);
+/// Gets notified of the entries created through `new`, and removed through `rm`.
+pub trait EntryObserver: Send + Sync {
+    /// Called once `name` got created out of `input`, which is what followed the name in `new`.
+    fn created(&self, name: &str, input: &str);
+    /// Called before `name` gets removed through `rm`, which is refused on error.
+    ///
+    /// `recursive` is set when `rm` was given `-r`, asking for whatever depends on the entry to
+    /// be removed along with it.
+    fn removing(&self, _name: &str, _recursive: bool) -> BuildResult<()> {
+        Ok(())
+    }
+    /// Called once `name` got written to `rm`, whether or not such entry existed.
+    fn removed(&self, name: &str);
+    /// Lock held from building an entry until `created` returns, and from calling `removing`
+    /// until `removed` returns.
+    ///
+    /// Observers of entries that depend on each other share a lock, so that no entry comes to
+    /// depend on another one while it gets removed.
+    fn lock(&self) -> Option<MutexGuard<'_, ()>> {
+        None
+    }
+}
+
+type Observer = Option<Arc<dyn EntryObserver>>;
+
+/// Failures of the last write to either `new` or `rm`, one per line, each along with its input.
+type LastError = Arc<Mutex<String>>;
+
+/// Contents of a leaf showing the failures kept in `last_error`.
+fn error_text(last_error: &LastError) -> TextFn {
+    let last_error = last_error.clone();
+    Arc::new(move || last_error.lock().unwrap().clone())
+}
+
+/// Describe the failure of `input` in the `new_error` or `rm_error` leaf.
+fn describe_failure(input: &[u8], err: &BuildError) -> String {
+    format!("{}: {}\n", String::from_utf8_lossy(input).trim(), err)
+}
+
+struct NewHandler<T: 'static, C: 'static> {
+    entries: CArcSome<DashMap<String, T>>,
+    context: CArc<C>,
+    build_fn: fn(&str, &CArc<C>) -> BuildResult<T>,
+    leaves: TextLeaves,
+    observer: Observer,
+    new_error: LastError,
+}
 
 impl<T, C> NewHandler<T, C> {
+    fn create(&self, input: &[u8]) -> BuildResult<()> {
+        let input = std::str::from_utf8(input).map_err(|_| {
+            BuildError::new(
+                Error(ErrorOrigin::Backend, ErrorKind::InvalidArgument),
+                "input is not valid UTF-8",
+            )
+        })?;
+
+        let (name, args) = input.split_once(' ').unwrap_or((input, ""));
+
+        let _lock = self.observer.as_ref().and_then(|o| o.lock());
+
+        if map_exists(&*self.entries, &self.leaves, name) {
+            return Err(BuildError::new(
+                Error(ErrorOrigin::Backend, ErrorKind::AlreadyExists),
+                format!("{name} already exists"),
+            ));
+        }
+
+        let obj = (self.build_fn)(args, &self.context)?;
+        map_checked_insert(&*self.entries, &self.leaves, name, obj)?;
+
+        if let Some(observer) = &self.observer {
+            observer.created(name, args);
+        }
+
+        Ok(())
+    }
+
     extern "C" fn write(&self, mut data: VecOps<ROData>) -> i32 {
+        let mut failures = String::new();
+
         for d in data.inp {
-            if let Err(e) = std::str::from_utf8(&d.1)
-                .map_err(|_| Error(ErrorOrigin::Backend, ErrorKind::InvalidArgument))
-                .map(|a| a.split_once(' ').unwrap_or((a, "")))
-                .and_then(|(n, a)| {
-                    if !map_exists(&*self.0, n) {
-                        Ok((n, a))
-                    } else {
-                        Err(Error(ErrorOrigin::Backend, ErrorKind::AlreadyExists))
-                    }
-                })
-                .and_then(|(n, a)| (self.2)(a, &self.1).map(|o| (n, o)))
-                .and_then(|(n, o)| map_checked_insert(&*self.0, n, o))
-            {
-                let _ = opt_call(data.out_fail.as_deref_mut(), (d, e).into());
+            if let Err(e) = self.create(&d.1) {
+                failures.push_str(&describe_failure(&d.1, &e));
+                let _ = opt_call(data.out_fail.as_deref_mut(), (d, e.error).into());
             }
         }
+
+        *self.new_error.lock().unwrap() = failures;
+
         0
     }
 }
 
-struct RmHandler<T: 'static>(CArcSome<DashMap<String, T>>);
+struct RmHandler<T: 'static> {
+    entries: CArcSome<DashMap<String, T>>,
+    observer: Observer,
+    rm_error: LastError,
+}
 
 impl<T> RmHandler<T> {
+    fn remove(&self, input: &[u8]) -> BuildResult<()> {
+        let input = std::str::from_utf8(input)
+            .map_err(|_| {
+                BuildError::new(
+                    Error(ErrorOrigin::Backend, ErrorKind::InvalidArgument),
+                    "input is not valid UTF-8",
+                )
+            })?
+            .trim();
+
+        let (recursive, name) = match input.strip_prefix("-r ") {
+            Some(name) => (true, name.trim()),
+            None => (false, input),
+        };
+
+        let _lock = self.observer.as_ref().and_then(|o| o.lock());
+
+        if let Some(observer) = &self.observer {
+            observer.removing(name, recursive)?;
+        }
+
+        let removed = self.entries.remove(name);
+
+        // Notified either way, so that stale records of the entry can be dropped
+        if let Some(observer) = &self.observer {
+            observer.removed(name);
+        }
+
+        match removed {
+            Some(_) => Ok(()),
+            None => Err(BuildError::new(
+                Error(ErrorOrigin::Backend, ErrorKind::NotFound),
+                format!("{name} does not exist"),
+            )),
+        }
+    }
+
     extern "C" fn write(&self, mut data: VecOps<ROData>) -> i32 {
+        let mut failures = String::new();
+
         for d in data.inp {
-            if let Err(e) = std::str::from_utf8(&d.1)
-                .map_err(|_| Error(ErrorOrigin::Backend, ErrorKind::InvalidArgument))
-                .map(|n| self.0.remove(n.trim()))
-            {
-                let _ = opt_call(data.out_fail.as_deref_mut(), (d, e).into());
+            if let Err(e) = self.remove(&d.1) {
+                failures.push_str(&describe_failure(&d.1, &e));
+                let _ = opt_call(data.out_fail.as_deref_mut(), (d, e.error).into());
             }
         }
+
+        *self.rm_error.lock().unwrap() = failures;
+
         0
     }
 }
@@ -94,7 +218,11 @@ pub struct LocalBackend<T: 'static, C: 'static = ()> {
     entries: CArcSome<DashMap<String, T>>,
     context: CArc<C>,
     handle_objs: RcSlab<FileOpsObj<c_void>>,
-    build_fn: Option<fn(&str, &CArc<C>) -> Result<T>>,
+    build_fn: Option<fn(&str, &CArc<C>) -> BuildResult<T>>,
+    observer: Observer,
+    new_error: LastError,
+    rm_error: LastError,
+    leaves: TextLeaves,
     new_handle: Result<usize>,
     rm_handle: Result<usize>,
 }
@@ -106,6 +234,13 @@ impl<T, C> Default for LocalBackend<T, C> {
 
         let handle_objs = Default::default();
 
+        let new_error = LastError::default();
+        let rm_error = LastError::default();
+        let leaves: TextLeaves = vec![
+            ("new_error", error_text(&new_error)),
+            ("rm_error", error_text(&rm_error)),
+        ];
+
         let mut ret = Self {
             entries,
             handle_objs,
@@ -113,6 +248,10 @@ impl<T, C> Default for LocalBackend<T, C> {
             new_handle: Err(Error(ErrorOrigin::Backend, ErrorKind::NotSupported)),
             rm_handle: Err(ErrorKind::Unknown.into()),
             build_fn: None,
+            observer: None,
+            new_error,
+            rm_error,
+            leaves,
         };
         ret.rebuild_rm();
         ret
@@ -140,11 +279,35 @@ impl<T, C> LocalBackend<T, C> {
         self.rebuild_new();
     }
 
+    /// Notify `observer` of the entries created and removed through the `new` and `rm` leaves.
+    ///
+    /// Entries inserted directly are not observed.
+    pub fn set_observer(&mut self, observer: impl EntryObserver + 'static) {
+        self.observer = Some(Arc::new(observer));
+        self.rebuild_rm();
+        self.rebuild_new();
+    }
+
+    /// Add a read-only leaf called `name` next to the entries, with the contents `text`
+    /// produces when the leaf gets opened.
+    pub fn add_text_leaf(
+        &mut self,
+        name: &'static str,
+        text: impl Fn() -> String + Send + Sync + 'static,
+    ) {
+        self.leaves.push((name, Arc::new(text)));
+        self.rebuild_new();
+    }
+
     pub fn rebuild_rm(&mut self) {
         if let Ok(rm_handle) = self.rm_handle {
             self.handle_objs.dec_rc(rm_handle);
         }
-        let rm_obj = RmHandler(self.entries.clone());
+        let rm_obj = RmHandler {
+            entries: self.entries.clone(),
+            observer: self.observer.clone(),
+            rm_error: self.rm_error.clone(),
+        };
         let rm_obj = FileOpsObj::new(rm_obj.into(), None, Some(RmHandler::write), None);
         let rm_handle = self
             .handle_objs
@@ -159,7 +322,14 @@ impl<T, C> LocalBackend<T, C> {
         }
 
         if let Some(build_fn) = self.build_fn {
-            let new_obj = NewHandler(self.entries.clone(), self.context.clone(), build_fn);
+            let new_obj = NewHandler {
+                entries: self.entries.clone(),
+                context: self.context.clone(),
+                build_fn,
+                leaves: self.leaves.clone(),
+                observer: self.observer.clone(),
+                new_error: self.new_error.clone(),
+            };
             let new_obj = FileOpsObj::new(new_obj.into(), None, Some(NewHandler::write), None);
             let new_handle = self
                 .handle_objs
@@ -173,6 +343,10 @@ impl<T, C> LocalBackend<T, C> {
         let Self {
             entries,
             handle_objs,
+            observer,
+            new_error,
+            rm_error,
+            leaves,
             new_handle,
             rm_handle,
             ..
@@ -182,6 +356,10 @@ impl<T, C> LocalBackend<T, C> {
             entries,
             handle_objs,
             context,
+            observer,
+            new_error,
+            rm_error,
+            leaves,
             new_handle,
             rm_handle,
             build_fn: None,
@@ -193,16 +371,34 @@ impl<T, C> LocalBackend<T, C> {
     }
 
     pub fn insert(&self, name: &str, entry: T) -> bool {
-        map_insert(&*self.entries, name, entry)
+        map_insert(&*self.entries, &self.leaves, name, entry)
     }
 
     pub fn checked_insert(&self, name: &str, entry: T) -> Result<()> {
-        map_checked_insert(&*self.entries, name, entry)
+        map_checked_insert(&*self.entries, &self.leaves, name, entry)
+    }
+
+    /// Remove an entry, without notifying the observer.
+    pub fn remove(&self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    /// Remove all entries, without notifying the observer.
+    pub fn clear(&self) {
+        self.entries.clear();
     }
 
     fn push_obj(&self, obj: FileOpsObj<c_void>) -> usize {
         self.handle_objs.insert(obj).unwrap()
     }
+
+    /// Text leaf called `name`, with its contents as of opening it.
+    fn text_leaf(&self, name: &str) -> Option<FnFile<TextFn, String>> {
+        self.leaves
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, text)| FnFile::new(text.clone(), |text| Ok(text())))
+    }
 }
 
 impl<T: Branch, C> Backend for LocalBackend<T, C> {
@@ -251,6 +447,8 @@ impl<T: Branch, C> Backend for LocalBackend<T, C> {
             self.new_handle
         } else if path.is_empty() && branch == "rm" {
             self.rm_handle
+        } else if let Some(leaf) = self.text_leaf(branch).filter(|_| path.is_empty()) {
+            leaf.open().map(|o| self.push_obj(o))
         } else {
             match self.entries.get(branch).map(|b| b.get_entry(path, plugins)) {
                 Some(Ok(DirEntry::Leaf(leaf))) => leaf.open().map(|o| self.push_obj(o)),
@@ -274,6 +472,8 @@ impl<T: Branch, C> Backend for LocalBackend<T, C> {
             self.new_handle.map(|_| NodeMetadata::default())
         } else if path.is_empty() && branch == "rm" {
             self.rm_handle.map(|_| NodeMetadata::default())
+        } else if let Some(leaf) = self.text_leaf(branch).filter(|_| path.is_empty()) {
+            leaf.metadata()
         } else {
             match self.entries.get(branch) {
                 Some(b) => {
@@ -307,6 +507,9 @@ impl<T: Branch, C> Backend for LocalBackend<T, C> {
                 .feed_into_mut(out);
 
             let _ = out.call(ListEntry::new("rm".into(), false));
+            for (name, _) in &self.leaves {
+                let _ = out.call(ListEntry::new((*name).into(), false));
+            }
 
             if self.new_handle.is_ok() {
                 let _ = out.call(ListEntry::new("new".into(), false));
@@ -332,6 +535,99 @@ impl<T: Branch, C> Backend for LocalBackend<T, C> {
     }
 }
 
+/// Exposes a single branch as a backend, for trees without any entries to create or remove.
+pub struct BranchBackend<T: 'static> {
+    root: T,
+    handle_objs: RcSlab<FileOpsObj<c_void>>,
+}
+
+impl<T> BranchBackend<T> {
+    pub fn new(root: T) -> Self {
+        Self {
+            root,
+            handle_objs: Default::default(),
+        }
+    }
+}
+
+impl<T: Branch> Backend for BranchBackend<T> {
+    fn read(&self, _stack: BackendStack, handle: usize, data: VecOps<RWData>) -> Result<()> {
+        match self.handle_objs.get(handle) {
+            Some(f) => f.read(data),
+            _ => Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+        }
+    }
+
+    fn write(&self, _stack: BackendStack, handle: usize, data: VecOps<ROData>) -> Result<()> {
+        match self.handle_objs.get(handle) {
+            Some(f) => f.write(data),
+            _ => Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+        }
+    }
+
+    fn rpc(
+        &self,
+        _stack: BackendStack,
+        handle: usize,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<()> {
+        match self.handle_objs.get(handle) {
+            Some(f) => f.rpc(input, output),
+            _ => Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+        }
+    }
+
+    fn close(&self, _stack: BackendStack, handle: usize) -> Result<()> {
+        match self.handle_objs.dec_rc(handle) {
+            Some(_) => Ok(()),
+            None => Err(Error(ErrorOrigin::Backend, ErrorKind::NotFound)),
+        }
+    }
+
+    fn open(&self, _stack: BackendStack, path: &str, plugins: &CPluginStore) -> Result<usize> {
+        match self.root.get_entry(path, plugins) {
+            Ok(DirEntry::Leaf(leaf)) => leaf.open().map(|o| self.handle_objs.insert(o).unwrap()),
+            Ok(_) => Err(Error(ErrorOrigin::Backend, ErrorKind::InvalidArgument)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn metadata(
+        &self,
+        _stack: BackendStack,
+        path: &str,
+        plugins: &CPluginStore,
+    ) -> Result<NodeMetadata> {
+        if path.is_empty() {
+            return Ok(NodeMetadata::branch());
+        }
+
+        match self.root.get_entry(path, plugins) {
+            Ok(DirEntry::Leaf(leaf)) => leaf.metadata(),
+            Ok(DirEntry::Branch(_)) => Ok(NodeMetadata::branch()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn list(
+        &self,
+        _stack: BackendStack,
+        path: &str,
+        plugins: &CPluginStore,
+        out: &mut OpaqueCallback<ListEntry>,
+    ) -> Result<()> {
+        let cb = &mut |entry: BranchListEntry| {
+            out.call(ListEntry::new(
+                entry.name,
+                matches!(entry.obj, DirEntry::Branch(_)),
+            ))
+        };
+
+        self.root.list_recurse(path, plugins, &mut cb.into())
+    }
+}
+
 #[repr(C)]
 #[derive(StableAbi)]
 pub enum BackendStack<'a> {
@@ -392,3 +688,118 @@ pub trait Backend {
         out: &mut OpaqueCallback<ListEntry>,
     ) -> Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    /// Entry that only builds out of `ok`.
+    struct Entry;
+
+    impl StrBuild<CArc<()>> for Entry {
+        fn build(input: &str, _: &CArc<()>) -> BuildResult<Self> {
+            match input {
+                "ok" => Ok(Self),
+                _ => Err(BuildError::new(
+                    ErrorKind::InvalidArgument,
+                    format!("{input} is not ok"),
+                )),
+            }
+        }
+    }
+
+    impl Branch for Entry {
+        fn get_entry(&self, _: &str, _: &CPluginStore) -> Result<DirEntry> {
+            Err(Error(ErrorOrigin::Branch, ErrorKind::NotFound))
+        }
+
+        fn list(&self, _: &CPluginStore, _: &mut OpaqueCallback<BranchListEntry>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write(node: &CArcSome<Node>, path: &str, data: &str) -> std::io::Result<()> {
+        let handle = node.open(path).unwrap();
+        ObjCursor::from((node, handle)).write_all(data.as_bytes())
+    }
+
+    fn read(node: &CArcSome<Node>, path: &str) -> String {
+        let handle = node.open(path).unwrap();
+        let mut out = String::new();
+        match ObjCursor::from((node, handle)).read_to_string(&mut out) {
+            // Reading nothing at all is reported as the end of the leaf
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => assert!(out.is_empty()),
+            ret => {
+                ret.unwrap();
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn new_error_describes_failure() {
+        let backend = LocalBackend::<Entry>::default().with_new();
+        let node: CArcSome<Node> = Node::new(backend).into();
+
+        assert!(write(&node, "new", "a bad").is_err());
+        assert_eq!(read(&node, "new_error"), "a bad: bad is not ok\n");
+
+        // Failing to remove an entry leaves the failure to create one alone
+        assert!(write(&node, "rm", "b").is_err());
+        assert_eq!(read(&node, "new_error"), "a bad: bad is not ok\n");
+        assert_eq!(read(&node, "rm_error"), "b: b does not exist\n");
+
+        write(&node, "new", "a ok").unwrap();
+        assert_eq!(read(&node, "new_error"), "");
+        assert!(node.metadata("a").is_ok());
+    }
+
+    /// Observer checking that it is notified with its lock held.
+    #[derive(Default)]
+    struct LockedObserver {
+        lock: Mutex<()>,
+        unlocked_calls: Mutex<Vec<String>>,
+    }
+
+    impl LockedObserver {
+        fn check(&self, call: &str) {
+            if self.lock.try_lock().is_ok() {
+                self.unlocked_calls.lock().unwrap().push(call.into());
+            }
+        }
+    }
+
+    impl EntryObserver for Arc<LockedObserver> {
+        fn created(&self, name: &str, _: &str) {
+            self.check(&format!("created {name}"));
+        }
+
+        fn removing(&self, name: &str, _: bool) -> BuildResult<()> {
+            self.check(&format!("removing {name}"));
+            Ok(())
+        }
+
+        fn removed(&self, name: &str) {
+            self.check(&format!("removed {name}"));
+        }
+
+        fn lock(&self) -> Option<MutexGuard<'_, ()>> {
+            Some(self.lock.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn observer_notified_locked() {
+        let observer = Arc::new(LockedObserver::default());
+        let mut backend = LocalBackend::<Entry>::default().with_new();
+        backend.set_observer(observer.clone());
+        let node: CArcSome<Node> = Node::new(backend).into();
+
+        write(&node, "new", "a ok").unwrap();
+        write(&node, "rm", "a").unwrap();
+
+        assert!(observer.unlocked_calls.lock().unwrap().is_empty());
+        assert!(observer.lock.try_lock().is_ok());
+    }
+}
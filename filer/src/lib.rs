@@ -11,7 +11,7 @@ pub mod types;
 pub mod prelude {
     pub mod v1 {
         pub use crate::{
-            backend::*, branch::*, error::*, fs::*, node::*, plugin_store::*, str_build::StrBuild,
+            backend::*, branch::*, error::*, fs::*, node::*, plugin_store::*, str_build::*,
             thread_ctx::*, types::*,
         };
     }
@@ -404,13 +404,15 @@ impl<'a, T: Frontend> From<(&'a T, usize, Size)> for ObjCursor<'a, T> {
     }
 }
 
+/// Perform a single IO operation, returning the amount of bytes processed before the first
+/// failure, along with its error.
 fn single_io<T: core::ops::Deref<Target = [u8]>>(
     func: impl Fn(VecOps<CTup2<u64, T>>) -> Result<()>,
     off: u64,
     buf: T,
-) -> Result<usize> {
+) -> Result<(usize, Option<Error>)> {
     let mut last_max = None;
-    let mut last_min = None;
+    let mut last_min: Option<(u64, Error)> = None;
 
     let out = &mut |d: CTup2<u64, T>| {
         let off = d.0 + d.1.len() as u64;
@@ -421,9 +423,11 @@ fn single_io<T: core::ops::Deref<Target = [u8]>>(
     let out = Some(out);
 
     let out_fail = &mut |d: FailData<CTup2<u64, T>>| {
-        let (d, _) = d.into();
+        let (d, e) = d.into();
         let off = d.0;
-        last_min = last_min.map(|r| core::cmp::min(off, r)).or(Some(off));
+        if last_min.is_none_or(|(r, _)| off < r) {
+            last_min = Some((off, e));
+        }
         true
     };
     let out_fail = &mut out_fail.into();
@@ -445,14 +449,16 @@ fn single_io<T: core::ops::Deref<Target = [u8]>>(
         // the case with memflow, thus this is sort of a hack until the API is there
         // TODO: remove when memflow has matching callback interface.
         .map(|_| last_max.unwrap_or(off + buf_len as u64))
-        .map(|maxio| core::cmp::min(last_min.unwrap_or(maxio), maxio))
-        .map(|lr| (lr - off) as usize)
+        .map(|maxio| match last_min {
+            Some((min, e)) if min <= maxio => ((min - off) as usize, Some(e)),
+            _ => ((maxio - off) as usize, None),
+        })
 }
 
 impl<'a, T: Frontend> std::io::Read for ObjCursor<'a, T> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let read = single_io(|data| self.0.read(data), self.1 .0, buf.into())
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.as_str()))?;
+        let (read, _) = single_io(|data| self.0.read(data), self.1 .0, buf.into())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
         self.1 .0 += read as Size;
 
@@ -471,8 +477,13 @@ impl<'a, T: Frontend> std::io::Read for ObjCursor<'a, T> {
 
 impl<'a, T: Frontend> std::io::Write for ObjCursor<'a, T> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let written = single_io(|data| self.0.write(data), self.1 .0, buf.into())
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.as_str()))?;
+        let (written, failure) = single_io(|data| self.0.write(data), self.1 .0, buf.into())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        // Nothing being written is only worth reporting as such without a reason
+        if let (0, Some(e)) = (written, failure) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
+        }
 
         self.1 .0 += written as Size;
 
@@ -1,5 +1,51 @@
 use crate::error::*;
 
+use std::fmt;
+
+/// Failure to build an object, along with a description of its cause for the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildError {
+    pub error: Error,
+    pub message: String,
+}
+
+impl BuildError {
+    pub fn new(error: impl Into<Error>, message: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<Error> for BuildError {
+    fn from(error: Error) -> Self {
+        Self::new(error, error.to_string())
+    }
+}
+
+impl From<ErrorKind> for BuildError {
+    fn from(kind: ErrorKind) -> Self {
+        Error::from(kind).into()
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(err: BuildError) -> Self {
+        err.error
+    }
+}
+
+pub type BuildResult<T> = std::result::Result<T, BuildError>;
+
 pub trait StrBuild<C>: Sized {
-    fn build(input: &str, ctx: &C) -> Result<Self>;
+    fn build(input: &str, ctx: &C) -> BuildResult<Self>;
 }
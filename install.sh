@@ -16,8 +16,8 @@ if [ ! -z "$1" ] && [ $1 = "--system" ]; then
 
         sudo systemctl stop memflow.service
 
-        sudo cp target/release/memflow-cli /usr/bin/memflow
-        sudo cp target/release/memflow-daemon /usr/bin/memflowd
+        sudo cp target/release/cloudflow-cli /usr/bin/cloudflow-cli
+        sudo cp target/release/cloudflow /usr/bin/cloudflow
 
         sudo mkdir -p /etc/memflow/
         sudo cp daemon.conf /etc/memflow/daemon.conf